@@ -0,0 +1,156 @@
+use crate::lox_error;
+use crate::parser;
+use crate::scanner;
+
+use lox_error::lox_error::LoxError;
+use parser::class::LoxInstance;
+use parser::environment::Environment;
+use parser::evaluate::{Evaluator, LoxType};
+use parser::heap::{address, Heap};
+use parser::parser::{ParseReturn, Stmt};
+use scanner::symbol::Symbol;
+use scanner::token::Token;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Anything that can sit to the left of a call's parentheses.  The evaluator
+// checks the arity before calling so call() can trust the argument count.
+pub trait Callable {
+    fn arity(&self) -> usize;
+    fn call(&self, evaluator: &mut Evaluator, arguments: Vec<LoxType>)
+        -> Result<LoxType, LoxError>;
+}
+
+pub struct LoxFunction {
+    name: Token,
+    params: Vec<Token>,
+    body: Rc<Vec<Stmt>>,
+    closure: Rc<RefCell<Environment>>,
+    // Initializers always hand back "this" no matter how they return
+    is_initializer: bool,
+}
+
+impl LoxFunction {
+    pub fn new(
+        name: &Token,
+        params: &[Token],
+        body: &Rc<Vec<Stmt>>,
+        closure: Rc<RefCell<Environment>>,
+        is_initializer: bool,
+    ) -> Self {
+        LoxFunction {
+            name: name.clone(),
+            params: params.to_vec(),
+            body: body.clone(),
+            closure,
+            is_initializer,
+        }
+    }
+
+    // Produces a copy of the method whose closure has "this" defined as the
+    // instance it was accessed through
+    pub fn bind(&self, instance: &Rc<RefCell<LoxInstance>>, heap: &mut Heap) -> Rc<LoxFunction> {
+        let mut environment = Environment::new_enclosed(self.closure.clone());
+        environment.define(&Symbol::intern("this"), LoxType::Instance(instance.clone()));
+        let closure = heap.environment(environment);
+        heap.function(LoxFunction {
+            name: self.name.clone(),
+            params: self.params.clone(),
+            body: self.body.clone(),
+            closure,
+            is_initializer: self.is_initializer,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name.lexeme
+    }
+
+    // For the collector - see heap.rs
+    pub fn references(&self, found: &mut Vec<usize>) {
+        found.push(address(&self.closure));
+    }
+}
+
+impl Callable for LoxFunction {
+    fn arity(&self) -> usize {
+        self.params.len()
+    }
+
+    fn call(
+        &self,
+        evaluator: &mut Evaluator,
+        arguments: Vec<LoxType>,
+    ) -> Result<LoxType, LoxError> {
+        let mut environment = Environment::new_enclosed(self.closure.clone());
+        for (param, argument) in self.params.iter().zip(arguments) {
+            environment.define(&param.lexeme, argument);
+        }
+
+        let result = evaluator.execute_block(&self.body, environment)?;
+        if self.is_initializer {
+            return Ok(self
+                .closure
+                .borrow()
+                .get_local(&Symbol::intern("this"))
+                .unwrap());
+        }
+
+        match result {
+            ParseReturn::Return(value) => Ok(value),
+            _ => Ok(LoxType::Nil),
+        }
+    }
+}
+
+// Two functions are only equal if they're the very same function
+impl PartialEq for LoxFunction {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+// The signature Rust code has to provide to be callable from Lox
+pub type NativeFn = dyn Fn(&[LoxType]) -> Result<LoxType, LoxError>;
+
+// A builtin implemented in Rust rather than Lox.  These get registered with
+// the evaluator by name - see Evaluator::define_native.
+pub struct NativeFunction {
+    name: String,
+    arity: usize,
+    function: Box<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new(name: &str, arity: usize, function: Box<NativeFn>) -> Self {
+        NativeFunction {
+            name: name.to_string(),
+            arity,
+            function,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Callable for NativeFunction {
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn call(
+        &self,
+        _evaluator: &mut Evaluator,
+        arguments: Vec<LoxType>,
+    ) -> Result<LoxType, LoxError> {
+        (self.function)(&arguments)
+    }
+}
+
+impl PartialEq for NativeFunction {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
@@ -0,0 +1,142 @@
+use crate::lox_error;
+use crate::parser;
+use crate::scanner;
+
+use lox_error::lox_error::LoxError;
+use parser::callable::{Callable, LoxFunction};
+use parser::evaluate::{Evaluator, LoxType};
+use parser::heap::{address, value_reference, Heap};
+use scanner::symbol::Symbol;
+use scanner::token::Token;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+pub struct LoxClass {
+    pub name: String,
+    superclass: Option<Rc<LoxClass>>,
+    methods: HashMap<Symbol, Rc<LoxFunction>>,
+}
+
+impl LoxClass {
+    pub fn new(
+        name: &str,
+        superclass: Option<Rc<LoxClass>>,
+        methods: HashMap<Symbol, Rc<LoxFunction>>,
+    ) -> Self {
+        LoxClass {
+            name: name.to_string(),
+            superclass,
+            methods,
+        }
+    }
+
+    // Methods we don't have ourselves are inherited from the superclass chain
+    pub fn find_method(&self, name: &Symbol) -> Option<Rc<LoxFunction>> {
+        match self.methods.get(name) {
+            Some(method) => Some(method.clone()),
+            None => match &self.superclass {
+                Some(superclass) => superclass.find_method(name),
+                None => None,
+            },
+        }
+    }
+
+    // For the collector - see heap.rs
+    pub fn references(&self, found: &mut Vec<usize>) {
+        if let Some(superclass) = &self.superclass {
+            found.push(address(superclass));
+        }
+        found.extend(self.methods.values().map(address));
+    }
+}
+
+// Calling a class creates an instance of it so the class itself is callable.
+// The instance has to point back at the class which means we need the Rc
+// rather than the bare class to make one.
+impl Callable for Rc<LoxClass> {
+    fn arity(&self) -> usize {
+        match self.find_method(&Symbol::intern("init")) {
+            Some(initializer) => initializer.arity(),
+            None => 0,
+        }
+    }
+
+    fn call(
+        &self,
+        evaluator: &mut Evaluator,
+        arguments: Vec<LoxType>,
+    ) -> Result<LoxType, LoxError> {
+        let instance = evaluator.heap().instance(LoxInstance::new(self.clone()));
+        if let Some(initializer) = self.find_method(&Symbol::intern("init")) {
+            initializer
+                .bind(&instance, evaluator.heap())
+                .call(evaluator, arguments)?;
+        }
+        Ok(LoxType::Instance(instance))
+    }
+}
+
+impl PartialEq for LoxClass {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+// Instances are shared by every variable that refers to them so they live
+// behind an Rc<RefCell<>> and a change through one is seen by all of them.
+pub struct LoxInstance {
+    pub class: Rc<LoxClass>,
+    fields: HashMap<Symbol, LoxType>,
+}
+
+impl LoxInstance {
+    pub fn new(class: Rc<LoxClass>) -> Self {
+        LoxInstance {
+            class,
+            fields: HashMap::new(),
+        }
+    }
+
+    // Fields shadow methods.  Methods come back bound to this instance.
+    pub fn get(
+        instance: &Rc<RefCell<LoxInstance>>,
+        name: &Token,
+        heap: &mut Heap,
+    ) -> Result<LoxType, LoxError> {
+        if let Some(value) = instance.borrow().fields.get(&name.lexeme) {
+            return Ok(value.clone());
+        }
+
+        let method = instance.borrow().class.find_method(&name.lexeme);
+        match method {
+            Some(method) => Ok(LoxType::Function(method.bind(instance, heap))),
+            None => Err(LoxError::new(
+                name.clone(),
+                &format!("Undefined property '{}'.", name.lexeme),
+            )),
+        }
+    }
+
+    pub fn set(&mut self, name: &Token, value: LoxType) {
+        self.fields.insert(name.lexeme.clone(), value);
+    }
+
+    // For the collector - see heap.rs
+    pub fn references(&self, found: &mut Vec<usize>) {
+        found.push(address(&self.class));
+        for value in self.fields.values() {
+            value_reference(value, found);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.fields.clear();
+    }
+}
+
+impl PartialEq for LoxInstance {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
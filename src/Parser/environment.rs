@@ -0,0 +1,162 @@
+use crate::lox_error;
+use crate::parser;
+use crate::scanner;
+
+use lox_error::lox_error::LoxError;
+use parser::evaluate::LoxType;
+use parser::heap::{address, value_reference};
+use scanner::symbol::Symbol;
+use scanner::token::Token;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// Environments get shared between the evaluator and any scopes nested inside
+// them so they live behind an Rc<RefCell<>>.  Each environment only knows about
+// the scope that encloses it - the globals are the one with no enclosing scope.
+// Names are interned so looking one up hashes a pointer rather than a string.
+pub struct Environment {
+    values: HashMap<Symbol, LoxType>,
+    enclosing: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Environment {
+            values: HashMap::new(),
+            enclosing: None,
+        }
+    }
+
+    pub fn new_enclosed(enclosing: Rc<RefCell<Environment>>) -> Self {
+        Environment {
+            values: HashMap::new(),
+            enclosing: Some(enclosing),
+        }
+    }
+
+    // Redefining an existing variable is legal and just replaces the old value
+    pub fn define(&mut self, name: &Symbol, value: LoxType) {
+        self.values.insert(name.clone(), value);
+    }
+
+    // Only looks in this scope - no walking out to the enclosing ones
+    pub fn get_local(&self, name: &Symbol) -> Option<LoxType> {
+        self.values.get(name).cloned()
+    }
+
+    pub fn get(&self, name: &Token) -> Result<LoxType, LoxError> {
+        if let Some(val) = self.values.get(&name.lexeme) {
+            return Ok(val.clone());
+        }
+
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow().get(name),
+            None => Err(undefined(name)),
+        }
+    }
+
+    pub fn assign(&mut self, name: &Token, value: LoxType) -> Result<(), LoxError> {
+        if let Some(val) = self.values.get_mut(&name.lexeme) {
+            *val = value;
+            return Ok(());
+        }
+
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign(name, value),
+            None => Err(undefined(name)),
+        }
+    }
+
+    // The resolver has already worked out how far up the chain the variable
+    // lives so we go straight there rather than searching for it
+    pub fn get_at(
+        env: &Rc<RefCell<Environment>>,
+        distance: usize,
+        name: &Token,
+    ) -> Result<LoxType, LoxError> {
+        match Self::ancestor(env, distance)
+            .borrow()
+            .values
+            .get(&name.lexeme)
+        {
+            Some(val) => Ok(val.clone()),
+            None => Err(undefined(name)),
+        }
+    }
+
+    pub fn assign_at(
+        env: &Rc<RefCell<Environment>>,
+        distance: usize,
+        name: &Token,
+        value: LoxType,
+    ) -> Result<(), LoxError> {
+        let ancestor = Self::ancestor(env, distance);
+        let mut ancestor = ancestor.borrow_mut();
+        match ancestor.values.get_mut(&name.lexeme) {
+            Some(val) => {
+                *val = value;
+                Ok(())
+            }
+            None => Err(undefined(name)),
+        }
+    }
+
+    // For the collector - see heap.rs
+    pub fn references(&self, found: &mut Vec<usize>) {
+        for value in self.values.values() {
+            value_reference(value, found);
+        }
+        if let Some(enclosing) = &self.enclosing {
+            found.push(address(enclosing));
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.enclosing = None;
+    }
+
+    fn ancestor(env: &Rc<RefCell<Environment>>, distance: usize) -> Rc<RefCell<Environment>> {
+        let mut environment = env.clone();
+        for _ in 0..distance {
+            let enclosing = environment
+                .borrow()
+                .enclosing
+                .clone()
+                .expect("Resolver gave a depth beyond the global scope");
+            environment = enclosing;
+        }
+        environment
+    }
+}
+
+fn undefined(name: &Token) -> LoxError {
+    LoxError::new(
+        name.clone(),
+        &format!("Undefined variable '{}'.", name.lexeme),
+    )
+}
+
+#[test]
+pub fn environment_test() {
+    use scanner::token_type::TokenType;
+
+    let name = Token::new(&TokenType::Identifier("a".into()), "a", 1);
+    let globals = Rc::new(RefCell::new(Environment::new()));
+    assert!(globals.borrow().get(&name).is_err());
+
+    globals
+        .borrow_mut()
+        .define(&name.lexeme, LoxType::Number(1.0));
+    let mut inner = Environment::new_enclosed(globals.clone());
+    assert!(inner.get(&name).ok() == Some(LoxType::Number(1.0)));
+
+    // Assignment walks out to the scope where the variable lives
+    assert!(inner.assign(&name, LoxType::Number(2.0)).is_ok());
+    assert!(globals.borrow().get(&name).ok() == Some(LoxType::Number(2.0)));
+
+    let missing = Token::new(&TokenType::Identifier("b".into()), "b", 3);
+    let err = inner.assign(&missing, LoxType::Nil).err().unwrap();
+    assert_eq!("3: at 'b' - Undefined variable 'b'.", err.report_msg());
+}
@@ -1,12 +1,33 @@
 use crate::lox_error;
 use crate::parser;
 use crate::scanner;
+use crate::vm;
 
 use lox_error::lox_error::{LoxError, LoxErrorList};
+use parser::callable::{Callable, LoxFunction, NativeFunction};
+use parser::class::{LoxClass, LoxInstance};
+use parser::environment::Environment;
+use parser::heap::Heap;
 use parser::parser::pstructs::Accept;
-use parser::parser::pstructs::{binary, grouping, literal, unary, Visitor};
-use parser::parser::ParseReturn;
-use scanner::{token::Token, token_type::TokenType};
+use parser::parser::pstructs::{
+    assign, binary, call, get, grouping, interpolation, literal, logical, set, super_expr, this,
+    unary, variable, Visitor,
+};
+use parser::parser::{ParseReturn, Stmt};
+use parser::statement::sstructs;
+use scanner::{
+    symbol::Symbol,
+    token::Token,
+    token_type::{NumberValue, TokenType},
+};
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{stdout, Write};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use vm::value::HeapStats;
 
 // I don't really see any reason I couldn't put the types of LoxType directly into
 // ParseReturn.  It would probably makes things both quicker and easier but it would
@@ -14,18 +35,29 @@ use scanner::{token::Token, token_type::TokenType};
 // class. To do otherwise would be non-orthogonal to the only current other visitor,
 // the pretty printer and just go against the idea behind ParseReturn which is a
 // replacement for generic parameters which I can't have on trait objects sadly.
-#[derive(PartialEq)]
+#[derive(Clone, PartialEq)]
 pub enum LoxType {
     Nil,
     Bool(bool),
     Number(f64),
-    String(String),
+    Integer(i64),
+    // Interned so copying one is cheap and == is a pointer comparison
+    String(Symbol),
+    Function(Rc<LoxFunction>),
+    Native(Rc<NativeFunction>),
+    Class(Rc<LoxClass>),
+    // Instances have reference semantics - assigning one to another variable
+    // gives you a second name for the same instance
+    Instance(Rc<RefCell<LoxInstance>>),
 }
 
 fn to_lox_type(tt: &TokenType) -> LoxType {
     match tt {
-        TokenType::Number(s) => LoxType::Number(str::parse::<f64>(s).unwrap()),
-        TokenType::String(s) => LoxType::String(s.to_string()),
+        TokenType::Number(_) => match tt.num_value() {
+            NumberValue::Integer(i) => LoxType::Integer(i),
+            NumberValue::Float(f) => LoxType::Number(f),
+        },
+        TokenType::String(s) => LoxType::String(s.clone()),
         TokenType::False => LoxType::Bool(false),
         TokenType::True => LoxType::Bool(true),
         TokenType::Nil => LoxType::Nil,
@@ -38,7 +70,11 @@ fn to_lox_name(val: &LoxType) -> &'static str {
         LoxType::Nil => "nil",
         LoxType::Bool(_) => "bool",
         LoxType::Number(_) => "number",
+        LoxType::Integer(_) => "integer",
         LoxType::String(_) => "string",
+        LoxType::Function(_) | LoxType::Native(_) => "function",
+        LoxType::Class(_) => "class",
+        LoxType::Instance(_) => "instance",
     }
 }
 
@@ -48,32 +84,156 @@ impl LoxType {
             LoxType::Nil => "nil".to_string(),
             LoxType::Bool(f) => format!("{}", f),
             LoxType::Number(n) => format!("{}", n),
-            LoxType::String(s) => s.clone(),
+            LoxType::Integer(i) => format!("{}", i),
+            LoxType::String(s) => s.to_string(),
+            LoxType::Function(f) => format!("<fn {}>", f.name()),
+            LoxType::Native(f) => format!("<native fn {}>", f.name()),
+            LoxType::Class(c) => c.name.clone(),
+            LoxType::Instance(i) => format!("{} instance", i.borrow().class.name),
+        }
+    }
+
+    // Handy for native functions which mostly don't care which sort of
+    // number they were given.  Only the tests define any of those so far.
+    #[cfg(test)]
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            LoxType::Number(n) => Some(*n),
+            LoxType::Integer(i) => Some(*i as f64),
+            _ => None,
         }
     }
 }
 
-pub struct Evaluator {}
+// The evaluator owns the global environment so anything defined in it lives
+// as long as the evaluator does - in the REPL that's across every line typed.
+// How deep calls can nest before it's a stack overflow.  The top level
+// counts as one so both backends give up at the same call.
+pub const FRAMES_MAX: usize = 4096;
+// Every Lox call recurses on the Rust stack so whatever runs the evaluator
+// has to give it a thread with this much room for FRAMES_MAX of them
+pub const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+pub struct Evaluator {
+    globals: Rc<RefCell<Environment>>,
+    environment: Rc<RefCell<Environment>>,
+    // Where print statements go - stdout unless someone wants to capture it
+    output: Box<dyn Write>,
+    // Calls to Lox code currently running, plus one for the top level
+    frames: usize,
+    // Where environments, functions, classes and instances get made
+    heap: Heap,
+}
 
 impl Evaluator {
+    pub fn new() -> Self {
+        Self::with_output(Box::new(stdout()))
+    }
+
+    pub fn with_output(output: Box<dyn Write>) -> Self {
+        let mut heap = Heap::new();
+        let globals = heap.environment(Environment::new());
+        let mut evaluator = Evaluator {
+            environment: globals.clone(),
+            globals,
+            output,
+            frames: 1,
+            heap,
+        };
+
+        evaluator.define_native("clock", 0, |_| {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(|_| LoxError::new_text_only(None, "System clock is before 1970"))?;
+            Ok(LoxType::Number(now.as_secs_f64()))
+        });
+        evaluator
+    }
+
+    // Lets the host program hand Lox builtins written in Rust.  They go in
+    // the globals so they can be shadowed like any other global.  The
+    // evaluator checks the argument count against arity before calling.
+    pub fn define_native<F>(&mut self, name: &str, arity: usize, function: F)
+    where
+        F: Fn(&[LoxType]) -> Result<LoxType, LoxError> + 'static,
+    {
+        let native = NativeFunction::new(name, arity, Box::new(function));
+        self.globals
+            .borrow_mut()
+            .define(&Symbol::intern(name), LoxType::Native(Rc::new(native)));
+    }
+
+    pub fn heap(&mut self) -> &mut Heap {
+        &mut self.heap
+    }
+
+    pub fn set_stress_gc(&mut self, stress_gc: bool) {
+        self.heap.set_stress_gc(stress_gc);
+    }
+
+    pub fn heap_stats(&self) -> HeapStats {
+        self.heap.stats()
+    }
+
     pub fn evaluate(&mut self, expr: &(dyn Accept + 'static)) -> Result<ParseReturn, LoxError> {
         expr.accept(self)
     }
 
-    pub fn interpret(&mut self, expr: &(dyn Accept + 'static)) -> LoxErrorList {
-        let eval_result = self.evaluate(expr);
-        let mut ret = LoxErrorList::new();
+    pub fn execute(
+        &mut self,
+        stmt: &(dyn sstructs::Accept + 'static),
+    ) -> Result<ParseReturn, LoxError> {
+        stmt.accept(self)
+    }
+
+    // The environment gets put back the way we found it whether the block
+    // finishes normally, returns or bails out with an error
+    pub fn execute_block(
+        &mut self,
+        statements: &[Stmt],
+        environment: Environment,
+    ) -> Result<ParseReturn, LoxError> {
+        let environment = self.heap.environment(environment);
+        let previous = std::mem::replace(&mut self.environment, environment);
 
-        match eval_result {
-            Err(l) => {
-                ret.push(l);
+        let mut result = Ok(ParseReturn::Unit);
+        for stmt in statements {
+            result = self.execute(&**stmt);
+            match result {
+                Ok(ParseReturn::Return(_)) | Err(_) => break,
+                _ => (),
             }
-            Ok(pr) => {
-                if let ParseReturn::Val(val) = pr {
-                    println!("{}", val.to_string());
-                } else {
-                    panic!("PR type other than Val from eval");
+        }
+
+        self.environment = previous;
+        result
+    }
+
+    // Anything the resolver didn't find a depth for has to be a global
+    fn look_up_variable(
+        &self,
+        name: &Token,
+        depth: &Cell<Option<usize>>,
+    ) -> Result<LoxType, LoxError> {
+        match depth.get() {
+            Some(distance) => Environment::get_at(&self.environment, distance, name),
+            None => self.globals.borrow().get(name),
+        }
+    }
+
+    pub fn interpret(&mut self, statements: &[Stmt]) -> LoxErrorList {
+        let mut ret = LoxErrorList::new();
+
+        // A runtime error ends the program so we only ever report one.  So
+        // does a return outside of any function.
+        for stmt in statements {
+            match self.execute(&**stmt) {
+                Err(l) => {
+                    ret.push(l);
+                    break;
                 }
+                Ok(ParseReturn::Return(_)) => break,
+                _ => (),
             }
         }
 
@@ -81,11 +241,280 @@ impl Evaluator {
     }
 }
 
+impl sstructs::Visitor for Evaluator {
+    fn expression(&mut self, stmt: &sstructs::expression) -> Result<ParseReturn, LoxError> {
+        self.evaluate(&*stmt.expression)?;
+        Ok(ParseReturn::Unit)
+    }
+
+    // The function hangs onto the environment it was declared in so it can
+    // still see those variables when it's called from somewhere else
+    fn function(&mut self, stmt: &sstructs::function) -> Result<ParseReturn, LoxError> {
+        let function = LoxFunction::new(
+            &stmt.name,
+            &stmt.params,
+            &stmt.body,
+            self.environment.clone(),
+            false,
+        );
+        let function = self.heap.function(function);
+        self.environment
+            .borrow_mut()
+            .define(&stmt.name.lexeme, LoxType::Function(function));
+        Ok(ParseReturn::Unit)
+    }
+
+    // With a superclass the methods close over an extra environment holding
+    // "super" which the resolver has already accounted for
+    fn class(&mut self, stmt: &sstructs::class) -> Result<ParseReturn, LoxError> {
+        let superclass = match &stmt.superclass {
+            None => None,
+            Some(superclass) => match get_value(self.variable(superclass)?) {
+                LoxType::Class(class) => Some(class),
+                _ => {
+                    return Err(LoxError::new(
+                        superclass.name.clone(),
+                        "Superclass must be a class.",
+                    ))
+                }
+            },
+        };
+
+        let previous = self.environment.clone();
+        if let Some(superclass) = &superclass {
+            let mut environment = Environment::new_enclosed(previous.clone());
+            environment.define(&Symbol::intern("super"), LoxType::Class(superclass.clone()));
+            self.environment = self.heap.environment(environment);
+        }
+
+        let mut methods = HashMap::new();
+        for method in &stmt.methods {
+            let function = LoxFunction::new(
+                &method.name,
+                &method.params,
+                &method.body,
+                self.environment.clone(),
+                method.name.lexeme == "init",
+            );
+            methods.insert(method.name.lexeme.clone(), self.heap.function(function));
+        }
+
+        self.environment = previous;
+        let class = self
+            .heap
+            .class(LoxClass::new(&stmt.name.lexeme, superclass, methods));
+        self.environment
+            .borrow_mut()
+            .define(&stmt.name.lexeme, LoxType::Class(class));
+        Ok(ParseReturn::Unit)
+    }
+
+    // Statements containing other statements hand back whatever their
+    // children do so a Return makes its way out to the function call
+    fn if_stmt(&mut self, stmt: &sstructs::if_stmt) -> Result<ParseReturn, LoxError> {
+        if is_truthy(&self.evaluate(&*stmt.condition)?) {
+            self.execute(&*stmt.then_branch)
+        } else if let Some(else_branch) = &stmt.else_branch {
+            self.execute(&**else_branch)
+        } else {
+            Ok(ParseReturn::Unit)
+        }
+    }
+
+    fn while_stmt(&mut self, stmt: &sstructs::while_stmt) -> Result<ParseReturn, LoxError> {
+        while is_truthy(&self.evaluate(&*stmt.condition)?) {
+            if let ParseReturn::Return(value) = self.execute(&*stmt.body)? {
+                return Ok(ParseReturn::Return(value));
+            }
+        }
+        Ok(ParseReturn::Unit)
+    }
+
+    fn return_stmt(&mut self, stmt: &sstructs::return_stmt) -> Result<ParseReturn, LoxError> {
+        let value = match &stmt.value {
+            Some(value) => get_value(self.evaluate(&**value)?),
+            None => LoxType::Nil,
+        };
+        Ok(ParseReturn::Return(value))
+    }
+
+    fn print(&mut self, stmt: &sstructs::print) -> Result<ParseReturn, LoxError> {
+        let val = get_value(self.evaluate(&*stmt.expression)?);
+        if let Err(err) = writeln!(self.output, "{}", val.to_string()) {
+            let msg = format!("Output problem: {:?}", err.to_string());
+            return Err(LoxError::new_text_only(None, &msg));
+        }
+        Ok(ParseReturn::Unit)
+    }
+
+    fn block(&mut self, stmt: &sstructs::block) -> Result<ParseReturn, LoxError> {
+        let environment = Environment::new_enclosed(self.environment.clone());
+        self.execute_block(&stmt.statements, environment)
+    }
+
+    fn var(&mut self, stmt: &sstructs::var) -> Result<ParseReturn, LoxError> {
+        let value = match &stmt.initializer {
+            Some(initializer) => get_value(self.evaluate(&**initializer)?),
+            None => LoxType::Nil,
+        };
+        self.environment
+            .borrow_mut()
+            .define(&stmt.name.lexeme, value);
+        Ok(ParseReturn::Unit)
+    }
+}
+
 impl Visitor for Evaluator {
     fn literal(&mut self, expr: &literal) -> Result<ParseReturn, LoxError> {
         Ok(ParseReturn::Val(to_lox_type(&expr.value)))
     }
 
+    fn variable(&mut self, expr: &variable) -> Result<ParseReturn, LoxError> {
+        Ok(ParseReturn::Val(
+            self.look_up_variable(&expr.name, &expr.depth)?,
+        ))
+    }
+
+    fn this(&mut self, expr: &this) -> Result<ParseReturn, LoxError> {
+        Ok(ParseReturn::Val(
+            self.look_up_variable(&expr.keyword, &expr.depth)?,
+        ))
+    }
+
+    // "super" lives one scope out from "this" - see class() above
+    fn super_expr(&mut self, expr: &super_expr) -> Result<ParseReturn, LoxError> {
+        let distance = expr.depth.get().expect("Resolver didn't resolve super");
+        let superclass = Environment::get_at(&self.environment, distance, &expr.keyword)?;
+        let this_token = Token::new(&TokenType::This, "this", expr.keyword.line);
+        let object = Environment::get_at(&self.environment, distance - 1, &this_token)?;
+
+        let method = match &superclass {
+            LoxType::Class(class) => class.find_method(&expr.method.lexeme),
+            _ => None,
+        };
+        match (method, object) {
+            (Some(method), LoxType::Instance(instance)) => Ok(ParseReturn::Val(LoxType::Function(
+                method.bind(&instance, &mut self.heap),
+            ))),
+            _ => Err(LoxError::new(
+                expr.method.clone(),
+                &format!("Undefined property '{}'.", expr.method.lexeme),
+            )),
+        }
+    }
+
+    fn get(&mut self, expr: &get) -> Result<ParseReturn, LoxError> {
+        match get_value(self.evaluate(&*expr.object)?) {
+            LoxType::Instance(instance) => {
+                let value = LoxInstance::get(&instance, &expr.name, &mut self.heap)?;
+                Ok(ParseReturn::Val(value))
+            }
+            _ => Err(
+                LoxError::new(expr.name.clone(), "Only instances have properties.")
+                    .with_span(expr.object.span()),
+            ),
+        }
+    }
+
+    fn set(&mut self, expr: &set) -> Result<ParseReturn, LoxError> {
+        let instance = match get_value(self.evaluate(&*expr.object)?) {
+            LoxType::Instance(instance) => instance,
+            _ => {
+                return Err(
+                    LoxError::new(expr.name.clone(), "Only instances have fields.")
+                        .with_span(expr.object.span()),
+                )
+            }
+        };
+
+        let value = get_value(self.evaluate(&*expr.value)?);
+        instance.borrow_mut().set(&expr.name, value.clone());
+        Ok(ParseReturn::Val(value))
+    }
+
+    fn assign(&mut self, expr: &assign) -> Result<ParseReturn, LoxError> {
+        let value = get_value(self.evaluate(&*expr.value)?);
+        match expr.depth.get() {
+            Some(distance) => {
+                Environment::assign_at(&self.environment, distance, &expr.name, value.clone())?
+            }
+            None => self
+                .globals
+                .borrow_mut()
+                .assign(&expr.name, value.clone())?,
+        };
+        Ok(ParseReturn::Val(value))
+    }
+
+    // Short circuits and hands back the deciding operand itself rather than
+    // a bool so "nil or 3" is 3
+    fn logical(&mut self, expr: &logical) -> Result<ParseReturn, LoxError> {
+        let left = self.evaluate(&*expr.left)?;
+
+        if expr.operator.ttype == TokenType::Or {
+            if is_truthy(&left) {
+                return Ok(left);
+            }
+        } else if !is_truthy(&left) {
+            return Ok(left);
+        }
+
+        self.evaluate(&*expr.right)
+    }
+
+    fn call(&mut self, expr: &call) -> Result<ParseReturn, LoxError> {
+        let callee = get_value(self.evaluate(&*expr.callee)?);
+
+        let mut arguments = vec![];
+        for argument in &expr.arguments {
+            arguments.push(get_value(self.evaluate(&**argument)?));
+        }
+
+        // Natives and classes without an initializer don't run any Lox code
+        // so they don't use up a frame
+        let (function, new_frame): (&dyn Callable, bool) = match &callee {
+            LoxType::Function(f) => (&**f, true),
+            LoxType::Native(f) => (&**f, false),
+            LoxType::Class(c) => (c, c.find_method(&Symbol::intern("init")).is_some()),
+            _ => {
+                return Err(LoxError::new(
+                    expr.paren.clone(),
+                    "Can only call functions and classes.",
+                )
+                .with_span(expr.callee.span()))
+            }
+        };
+
+        if arguments.len() != function.arity() {
+            let msg = format!(
+                "Expected {} arguments but got {}.",
+                function.arity(),
+                arguments.len()
+            );
+            return Err(LoxError::new(expr.paren.clone(), &msg).with_span(expr.span));
+        }
+
+        if !new_frame {
+            return Ok(ParseReturn::Val(function.call(self, arguments)?));
+        }
+        if self.frames == FRAMES_MAX {
+            return Err(LoxError::new(expr.paren.clone(), "Stack overflow.").with_span(expr.span));
+        }
+        self.frames += 1;
+        let result = function.call(self, arguments);
+        self.frames -= 1;
+        Ok(ParseReturn::Val(result?))
+    }
+
+    // Each part gets turned into a string just the way print would do it
+    fn interpolation(&mut self, expr: &interpolation) -> Result<ParseReturn, LoxError> {
+        let mut result = String::new();
+        for part in &expr.parts {
+            result += &get_value(self.evaluate(&**part)?).to_string();
+        }
+        Ok(ParseReturn::Val(LoxType::String(Symbol::intern(&result))))
+    }
+
     fn grouping(&mut self, expr: &grouping) -> Result<ParseReturn, LoxError> {
         Ok(self.evaluate(&*expr.expression)?)
     }
@@ -94,82 +523,266 @@ impl Visitor for Evaluator {
         let right = self.evaluate(&*expr.right)?;
         match expr.operator.ttype {
             TokenType::Minus => {
-                let right_val = get_number(&right, &expr.operator)?;
-                Ok(ParseReturn::Val(LoxType::Number(-right_val)))
+                let value = match get_number(&right, &expr.operator) {
+                    Ok(NumberValue::Integer(i)) => match i.checked_neg() {
+                        Some(negated) => LoxType::Integer(negated),
+                        None => {
+                            return Err(LoxError::new(expr.operator.clone(), "Integer overflow.")
+                                .with_span(expr.span))
+                        }
+                    },
+                    Ok(NumberValue::Float(f)) => LoxType::Number(-f),
+                    Err(e) => return Err(e.with_span(expr.span)),
+                };
+                Ok(ParseReturn::Val(value))
             }
-            TokenType::Bang => {
-                let right_val = get_bool(&right, &expr.operator)?;
-                Ok(ParseReturn::Val(LoxType::Bool(!right_val)))
+            TokenType::Tilde => {
+                let value =
+                    get_integer(&right, &expr.operator).map_err(|e| e.with_span(expr.span))?;
+                Ok(ParseReturn::Val(LoxType::Integer(!value)))
             }
+            TokenType::Bang => Ok(ParseReturn::Val(LoxType::Bool(!is_truthy(&right)))),
             // Don't think the parser will allow this case to happen
             _ => panic!("Unary with invalid operation in Eval"),
         }
     }
 
-    // copious error handling involved in here...
+    // Type errors underline the whole expression rather than just the operator
     fn binary(&mut self, expr: &binary) -> Result<ParseReturn, LoxError> {
         let left = self.evaluate(&*expr.left)?;
         let right = self.evaluate(&*expr.right)?;
-        let token = &expr.operator;
-        match expr.operator.ttype {
-            TokenType::Minus => {
-                let (left_val, right_val) = get_numeric_values(&left, &right, token)?;
-                Ok(ParseReturn::Val(LoxType::Number(left_val - right_val)))
-            }
+        apply_binary(&left, &right, &expr.operator).map_err(|e| e.with_span(expr.span))
+    }
+}
 
-            TokenType::Slash => {
-                let (left_val, right_val) = get_numeric_values(&left, &right, token)?;
-                Ok(ParseReturn::Val(LoxType::Number(left_val / right_val)))
-            }
+// copious error handling involved in here...
+//
+// Integers stay integers under + - * ~/ and % but anything involving a
+// float becomes a float.  / always gives a float so 7 / 2 is 3.5 - use ~/
+// for integer division.  Integer results that don't fit are errors rather
+// than quietly wrapping.
+fn apply_binary(
+    left: &ParseReturn,
+    right: &ParseReturn,
+    token: &Token,
+) -> Result<ParseReturn, LoxError> {
+    match token.ttype {
+        TokenType::Minus => arithmetic(
+            left,
+            right,
+            token,
+            |a, b| checked(a.checked_sub(b)),
+            |a, b| a - b,
+        ),
 
-            TokenType::Star => {
-                let (left_val, right_val) = get_numeric_values(&left, &right, token)?;
-                Ok(ParseReturn::Val(LoxType::Number(left_val * right_val)))
-            }
+        TokenType::Slash => {
+            let (left_val, right_val) = get_numeric_values(left, right, token)?;
+            Ok(ParseReturn::Val(LoxType::Number(
+                left_val.as_f64() / right_val.as_f64(),
+            )))
+        }
 
-            TokenType::Plus => {
-                if is_numeric(&left) && is_numeric(&right) {
-                    let (left_val, right_val) = get_numeric_values(&left, &right, token)?;
-                    Ok(ParseReturn::Val(LoxType::Number(left_val + right_val)))
-                } else if is_string(&left) && is_string(&right) {
-                    let (left_val, right_val) = get_string_values(&left, &right, token)?;
-                    let concat = format!("{}{}", left_val, right_val);
-                    Ok(ParseReturn::Val(LoxType::String(concat)))
-                } else {
-                    Err(LoxError::new(token.clone(), "Mismatched types"))
-                }
-            }
+        TokenType::TildeSlash => arithmetic(
+            left,
+            right,
+            token,
+            |a, b| match b {
+                0 => Err("Division by zero."),
+                _ => checked(floor_div(a, b)),
+            },
+            |a, b| (a / b).floor(),
+        ),
 
-            TokenType::Greater => {
-                let (left_val, right_val) = get_numeric_values(&left, &right, token)?;
-                Ok(ParseReturn::Val(LoxType::Bool(left_val > right_val)))
-            }
+        TokenType::Percent => arithmetic(
+            left,
+            right,
+            token,
+            |a, b| match b {
+                0 => Err("Division by zero."),
+                _ => Ok(floor_mod(a, b)),
+            },
+            |a, b| a - b * (a / b).floor(),
+        ),
 
-            TokenType::Less => {
-                let (left_val, right_val) = get_numeric_values(&left, &right, token)?;
-                Ok(ParseReturn::Val(LoxType::Bool(left_val < right_val)))
-            }
+        TokenType::Star => arithmetic(
+            left,
+            right,
+            token,
+            |a, b| checked(a.checked_mul(b)),
+            |a, b| a * b,
+        ),
 
-            TokenType::GreaterEqual => {
-                let (left_val, right_val) = get_numeric_values(&left, &right, token)?;
-                Ok(ParseReturn::Val(LoxType::Bool(left_val >= right_val)))
+        // A negative integer power can't stay an integer so it goes to float
+        TokenType::StarStar => match get_numeric_values(left, right, token)? {
+            (NumberValue::Integer(a), NumberValue::Integer(b)) if b >= 0 => {
+                let result = checked(integer_power(a, b))
+                    .map_err(|msg| LoxError::new(token.clone(), msg))?;
+                Ok(ParseReturn::Val(LoxType::Integer(result)))
             }
+            (a, b) => Ok(ParseReturn::Val(LoxType::Number(
+                a.as_f64().powf(b.as_f64()),
+            ))),
+        },
+
+        // Bitwise operators only make sense on integers and work on the bits
+        // as they are - there's no overflow to worry about
+        TokenType::Ampersand => bitwise(left, right, token, |a, b| Ok(a & b)),
+        TokenType::Pipe => bitwise(left, right, token, |a, b| Ok(a | b)),
+        TokenType::Caret => bitwise(left, right, token, |a, b| Ok(a ^ b)),
+        TokenType::LessLess => bitwise(left, right, token, |a, b| shift_amount(b).map(|b| a << b)),
+        // Arithmetic shift so negative numbers stay negative
+        TokenType::GreaterGreater => {
+            bitwise(left, right, token, |a, b| shift_amount(b).map(|b| a >> b))
+        }
 
-            TokenType::LessEqual => {
-                let (left_val, right_val) = get_numeric_values(&left, &right, token)?;
-                Ok(ParseReturn::Val(LoxType::Bool(left_val <= right_val)))
+        TokenType::Plus => {
+            if is_numeric(left) && is_numeric(right) {
+                arithmetic(
+                    left,
+                    right,
+                    token,
+                    |a, b| checked(a.checked_add(b)),
+                    |a, b| a + b,
+                )
+            } else if is_string(left) && is_string(right) {
+                let (left_val, right_val) = get_string_values(left, right, token)?;
+                let concat = format!("{}{}", left_val, right_val);
+                Ok(ParseReturn::Val(LoxType::String(Symbol::intern(&concat))))
+            } else {
+                Err(LoxError::new(token.clone(), "Mismatched types"))
             }
+        }
 
-            // We do follow IEEE 754 for NaN here.  The book does not.  Not going to "fix" this.
-            TokenType::EqualEqual => Ok(ParseReturn::Val(LoxType::Bool(is_equal(
-                &left, &right, token,
-            )?))),
+        TokenType::Greater => comparison(left, right, token, Ordering::is_gt),
+        TokenType::Less => comparison(left, right, token, Ordering::is_lt),
+        TokenType::GreaterEqual => comparison(left, right, token, Ordering::is_ge),
+        TokenType::LessEqual => comparison(left, right, token, Ordering::is_le),
 
-            TokenType::BangEqual => Ok(ParseReturn::Val(LoxType::Bool(!is_equal(
-                &left, &right, token,
-            )?))),
+        // We do follow IEEE 754 for NaN here.  The book does not.  Not going to "fix" this.
+        TokenType::EqualEqual => Ok(ParseReturn::Val(LoxType::Bool(is_equal(
+            left, right, token,
+        )?))),
 
-            _ => panic!("Unhandled operator in binary"),
+        TokenType::BangEqual => Ok(ParseReturn::Val(LoxType::Bool(!is_equal(
+            left, right, token,
+        )?))),
+
+        _ => panic!("Unhandled operator in binary"),
+    }
+}
+
+fn arithmetic(
+    left: &ParseReturn,
+    right: &ParseReturn,
+    token: &Token,
+    int_op: fn(i64, i64) -> Result<i64, &'static str>,
+    float_op: fn(f64, f64) -> f64,
+) -> Result<ParseReturn, LoxError> {
+    let result = match get_numeric_values(left, right, token)? {
+        (NumberValue::Integer(a), NumberValue::Integer(b)) => {
+            LoxType::Integer(int_op(a, b).map_err(|msg| LoxError::new(token.clone(), msg))?)
+        }
+        (a, b) => LoxType::Number(float_op(a.as_f64(), b.as_f64())),
+    };
+    Ok(ParseReturn::Val(result))
+}
+
+fn bitwise(
+    left: &ParseReturn,
+    right: &ParseReturn,
+    token: &Token,
+    op: fn(i64, i64) -> Result<i64, &'static str>,
+) -> Result<ParseReturn, LoxError> {
+    let (left_val, right_val) = get_integer_values(left, right, token)?;
+    let result = op(left_val, right_val).map_err(|msg| LoxError::new(token.clone(), msg))?;
+    Ok(ParseReturn::Val(LoxType::Integer(result)))
+}
+
+pub fn shift_amount(amount: i64) -> Result<u32, &'static str> {
+    match amount {
+        0..=63 => Ok(amount as u32),
+        _ => Err("Shift amount must be between 0 and 63."),
+    }
+}
+
+// NaN isn't ordered against anything so every comparison with it is false
+fn comparison(
+    left: &ParseReturn,
+    right: &ParseReturn,
+    token: &Token,
+    test: fn(Ordering) -> bool,
+) -> Result<ParseReturn, LoxError> {
+    let (left_val, right_val) = get_numeric_values(left, right, token)?;
+    let result = compare_numbers(left_val, right_val).is_some_and(test);
+    Ok(ParseReturn::Val(LoxType::Bool(result)))
+}
+
+pub fn checked(result: Option<i64>) -> Result<i64, &'static str> {
+    result.ok_or("Integer overflow.")
+}
+
+// Integer division and modulo round towards negative infinity so that
+// a == (a ~/ b) * b + a % b always holds and a % b has the sign of b
+pub fn floor_div(a: i64, b: i64) -> Option<i64> {
+    let quotient = a.checked_div(b)?;
+    if a % b != 0 && (a < 0) != (b < 0) {
+        Some(quotient - 1)
+    } else {
+        Some(quotient)
+    }
+}
+
+// For a non-negative power.  Bases 0, 1 and -1 never overflow however big
+// the power is so they're sorted out before it has to fit checked_pow.
+pub fn integer_power(a: i64, b: i64) -> Option<i64> {
+    match a {
+        0 if b == 0 => Some(1),
+        0 => Some(0),
+        1 => Some(1),
+        -1 if b % 2 == 0 => Some(1),
+        -1 => Some(-1),
+        _ => u32::try_from(b).ok().and_then(|b| a.checked_pow(b)),
+    }
+}
+
+pub fn floor_mod(a: i64, b: i64) -> i64 {
+    // The only way checked_rem can fail is i64::MIN % -1 which is 0 anyway
+    let remainder = a.checked_rem(b).unwrap_or(0);
+    if remainder != 0 && (remainder < 0) != (b < 0) {
+        remainder + b
+    } else {
+        remainder
+    }
+}
+
+// Compares the actual values so that 2^53 + 1 isn't equal to the float
+// 2^53 just because converting it to a float would round it down
+pub fn compare_numbers(left: NumberValue, right: NumberValue) -> Option<Ordering> {
+    match (left, right) {
+        (NumberValue::Integer(a), NumberValue::Integer(b)) => Some(a.cmp(&b)),
+        (NumberValue::Float(a), NumberValue::Float(b)) => a.partial_cmp(&b),
+        (NumberValue::Integer(a), NumberValue::Float(b)) => compare_int_float(a, b),
+        (NumberValue::Float(a), NumberValue::Integer(b)) => {
+            compare_int_float(b, a).map(Ordering::reverse)
+        }
+    }
+}
+
+fn compare_int_float(i: i64, f: f64) -> Option<Ordering> {
+    // 2^63 is exactly representable as a float so these are exact too
+    const LIMIT: f64 = 9_223_372_036_854_775_808.0;
+    if f.is_nan() {
+        None
+    } else if f >= LIMIT {
+        Some(Ordering::Less)
+    } else if f < -LIMIT {
+        Some(Ordering::Greater)
+    } else {
+        // Compare the whole parts and if they're the same the fraction decides
+        let whole = f.trunc();
+        match i.cmp(&(whole as i64)) {
+            Ordering::Equal => 0.0.partial_cmp(&(f - whole)),
+            ordering => Some(ordering),
         }
     }
 }
@@ -179,9 +792,27 @@ impl Visitor for Evaluator {
 // Functions to retrieve/manipulate LoxTypes, ParseResults and actual values
 //
 /////////////////////////////////////////////////////////////////////////////
-fn get_number(pr: &ParseReturn, token: &Token) -> Result<f64, LoxError> {
+fn get_value(pr: ParseReturn) -> LoxType {
     match pr {
-        ParseReturn::Val(LoxType::Number(n)) => Ok(*n),
+        ParseReturn::Val(val) => val,
+        _ => panic!("No LoxType in eval"),
+    }
+}
+
+// Lox follows Ruby - false and nil are falsey and everything else is truthy
+fn is_truthy(pr: &ParseReturn) -> bool {
+    match pr {
+        ParseReturn::Val(LoxType::Nil) => false,
+        ParseReturn::Val(LoxType::Bool(f)) => *f,
+        ParseReturn::Val(_) => true,
+        _ => panic!("No LoxType in eval"),
+    }
+}
+
+fn get_number(pr: &ParseReturn, token: &Token) -> Result<NumberValue, LoxError> {
+    match pr {
+        ParseReturn::Val(LoxType::Number(n)) => Ok(NumberValue::Float(*n)),
+        ParseReturn::Val(LoxType::Integer(i)) => Ok(NumberValue::Integer(*i)),
         ParseReturn::Val(val) => {
             let err_msg = format!("Expected number but found {}", to_lox_name(&val));
             Err(LoxError::new(token.clone(), &err_msg))
@@ -190,6 +821,17 @@ fn get_number(pr: &ParseReturn, token: &Token) -> Result<f64, LoxError> {
     }
 }
 
+fn get_integer(pr: &ParseReturn, token: &Token) -> Result<i64, LoxError> {
+    match pr {
+        ParseReturn::Val(LoxType::Integer(i)) => Ok(*i),
+        ParseReturn::Val(val) => {
+            let err_msg = format!("Expected integer but found {}", to_lox_name(val));
+            Err(LoxError::new(token.clone(), &err_msg))
+        }
+        _ => panic!("No LoxType in eval"),
+    }
+}
+
 fn get_bool(pr: &ParseReturn, token: &Token) -> Result<bool, LoxError> {
     match pr {
         ParseReturn::Val(LoxType::Bool(f)) => Ok(*f),
@@ -201,11 +843,10 @@ fn get_bool(pr: &ParseReturn, token: &Token) -> Result<bool, LoxError> {
     }
 }
 
-fn get_string(pr: &ParseReturn, token: &Token) -> Result<String, LoxError> {
+fn get_string(pr: &ParseReturn, token: &Token) -> Result<Symbol, LoxError> {
     match pr {
-        // Now that we're actually evaluating we may have to eventually
-        // mutate this string so we make a copy instead of using it
-        // directly
+        // Strings are interned and never mutated so this only copies a
+        // pointer
         ParseReturn::Val(LoxType::String(s)) => Ok(s.clone()),
         ParseReturn::Val(val) => {
             let err_msg = format!("Expected string but found {}", to_lox_name(&val));
@@ -219,17 +860,27 @@ fn get_numeric_values(
     left: &ParseReturn,
     right: &ParseReturn,
     token: &Token,
-) -> Result<(f64, f64), LoxError> {
+) -> Result<(NumberValue, NumberValue), LoxError> {
     let left_val = get_number(&left, token)?;
     let right_val = get_number(&right, token)?;
     Ok((left_val, right_val))
 }
 
+fn get_integer_values(
+    left: &ParseReturn,
+    right: &ParseReturn,
+    token: &Token,
+) -> Result<(i64, i64), LoxError> {
+    let left_val = get_integer(left, token)?;
+    let right_val = get_integer(right, token)?;
+    Ok((left_val, right_val))
+}
+
 fn get_string_values(
     left: &ParseReturn,
     right: &ParseReturn,
     token: &Token,
-) -> Result<(String, String), LoxError> {
+) -> Result<(Symbol, Symbol), LoxError> {
     let left_val = get_string(&left, token)?;
     let right_val = get_string(&right, token)?;
     Ok((left_val, right_val))
@@ -251,7 +902,7 @@ fn is_nil(pr: &ParseReturn) -> bool {
 
 fn is_numeric(pr: &ParseReturn) -> bool {
     match pr {
-        ParseReturn::Val(LoxType::Number(_)) => true,
+        ParseReturn::Val(LoxType::Number(_)) | ParseReturn::Val(LoxType::Integer(_)) => true,
         _ => false,
     }
 }
@@ -270,13 +921,23 @@ fn is_bool(pr: &ParseReturn) -> bool {
     }
 }
 
+fn is_object(pr: &ParseReturn) -> bool {
+    matches!(
+        pr,
+        ParseReturn::Val(LoxType::Function(_))
+            | ParseReturn::Val(LoxType::Native(_))
+            | ParseReturn::Val(LoxType::Class(_))
+            | ParseReturn::Val(LoxType::Instance(_))
+    )
+}
+
 fn is_equal(left: &ParseReturn, right: &ParseReturn, token: &Token) -> Result<bool, LoxError> {
     if is_numeric(left) {
         if !is_numeric(right) {
             return Ok(false);
         }
         let (left_val, right_val) = get_numeric_values(left, right, token)?;
-        return Ok(left_val == right_val);
+        return Ok(compare_numbers(left_val, right_val) == Some(Ordering::Equal));
     };
 
     if is_string(left) {
@@ -295,6 +956,12 @@ fn is_equal(left: &ParseReturn, right: &ParseReturn, token: &Token) -> Result<bo
         return Ok(left_val == right_val);
     };
 
+    // Functions, classes and instances are only equal to themselves which is
+    // exactly how their PartialEq works
+    if is_object(left) || is_object(right) {
+        return Ok(left == right);
+    }
+
     let is_nil_left = is_nil(left);
     let is_nil_right = is_nil(right);
     if is_nil_left && is_nil_right {
@@ -308,3 +975,443 @@ fn is_equal(left: &ParseReturn, right: &ParseReturn, token: &Token) -> Result<bo
     // Should never reach here...
     panic!("Equals didn't handle all cases");
 }
+
+#[test]
+pub fn control_flow_test() {
+    let (output, errors) = run_program(
+        "print !nil;
+        print !0;
+        print nil or \"default\";
+        print 1 and 2;
+        print false and undefined;
+        if (nil) print \"then\"; else print \"else\";
+        if (\"\") if (false) print 1; else print 2;
+        var i = 0;
+        while (i < 3) i = i + 1;
+        print i;
+        var sum = 0;
+        for (var j = 0; j < 4; j = j + 1) sum = sum + j;
+        print sum;",
+    );
+    assert_eq!(0, errors.len());
+    assert_eq!("true\nfalse\ndefault\n2\nfalse\nelse\n2\n3\n6\n", output);
+}
+
+#[test]
+pub fn function_test() {
+    let (output, errors) = run_program(
+        "fun fib(n) {
+            if (n < 2) return n;
+            return fib(n - 1) + fib(n - 2);
+        }
+        print fib(10);
+
+        fun find(limit) {
+            for (var i = 0; i < limit; i = i + 1) {
+                while (true) {
+                    if (i == 3) return i;
+                    i = i + 1;
+                }
+            }
+            return \"none\";
+        }
+        print find(10);
+
+        fun nothing() {}
+        print nothing();
+        print fib;
+        print fib == fib;",
+    );
+    assert_eq!(0, errors.len());
+    assert_eq!("55\n3\nnil\n<fn fib>\ntrue\n", output);
+
+    let (_, errors) = run_program("fun f(a, b) {}\nf(1);");
+    assert_eq!(1, errors.len());
+    let (_, errors) = run_program("\"not a function\"();");
+    assert_eq!(1, errors.len());
+}
+
+#[test]
+pub fn closure_test() {
+    let (output, errors) = run_program(
+        "fun makeCounter() {
+            var i = 0;
+            fun count() {
+                i = i + 1;
+                return i;
+            }
+            return count;
+        }
+        var counter = makeCounter();
+        print counter();
+        print counter();
+
+        // Each call to makeCounter gets its own i
+        var other = makeCounter();
+        print other();
+        print counter();",
+    );
+    assert_eq!(0, errors.len());
+    assert_eq!("1\n2\n1\n3\n", output);
+
+    // Closures made in the same call share the variables they capture
+    let (output, errors) = run_program(
+        "var get;
+        var set;
+        fun pair() {
+            var value = \"before\";
+            fun getter() { return value; }
+            fun setter(v) { value = v; }
+            get = getter;
+            set = setter;
+        }
+        pair();
+        print get();
+        set(\"after\");
+        print get();",
+    );
+    assert_eq!(0, errors.len());
+    assert_eq!("before\nafter\n", output);
+}
+
+#[test]
+pub fn class_test() {
+    let (output, errors) = run_program(
+        "class Point {
+            init(x, y) {
+                this.x = x;
+                this.y = y;
+            }
+            sum() { return this.x + this.y; }
+        }
+        class Early {
+            init() {
+                return;
+                print \"unreachable\";
+            }
+        }
+        var p = Point(1, 2);
+        print p.sum();
+        var alias = p;
+        alias.x = 10;
+        print p.sum();
+
+        // A method pulled off an instance stays bound to it
+        var sum = p.sum;
+        print sum();
+        print Point;
+        print p;
+        print p.init(3, 4) == p;
+        print Early();
+        print alias == p;
+        print Point(1, 2) == Point(1, 2);",
+    );
+    assert_eq!(0, errors.len());
+    assert_eq!(
+        "3\n12\n12\nPoint\nPoint instance\ntrue\nEarly instance\ntrue\nfalse\n",
+        output
+    );
+
+    let (_, errors) = run_program("class A {} A().missing;");
+    assert_eq!(1, errors.len());
+    let (_, errors) = run_program("var a = 1; a.field = 2;");
+    assert_eq!(1, errors.len());
+    let (_, errors) = run_program("print this;");
+    assert_eq!(1, errors.len());
+    let (_, errors) = run_program("class A { init() { return 1; } }");
+    assert_eq!(1, errors.len());
+}
+
+#[test]
+pub fn inheritance_test() {
+    let (output, errors) = run_program(
+        "class A {
+            method() { return \"A method\"; }
+            name() { return \"A \" + this.tag; }
+        }
+        class B < A {
+            init(tag) { this.tag = tag; }
+            method() { return \"B method\"; }
+            test() { return super.method(); }
+        }
+        class C < B {
+            method() { return super.method() + \" via C\"; }
+        }
+        var c = C(\"c\");
+        print c.method();
+        print c.test();
+        print c.name();
+
+        // super binds this to the instance the method was called on
+        class Base { who() { return this.id; } }
+        class Derived < Base { who() { return \"derived \" + super.who(); } }
+        var d = Derived();
+        d.id = \"d\";
+        print d.who();",
+    );
+    assert_eq!(0, errors.len());
+    assert_eq!("B method via C\nA method\nA c\nderived d\n", output);
+
+    let (_, errors) = run_program("var NotAClass = 1; class A < NotAClass {}");
+    assert_eq!(1, errors.len());
+    let (_, errors) = run_program("class A < A {}");
+    assert_eq!(1, errors.len());
+    let (_, errors) = run_program("class A { f() { super.f(); } }");
+    assert_eq!(1, errors.len());
+    let (_, errors) = run_program("super.f();");
+    assert_eq!(1, errors.len());
+}
+
+#[test]
+pub fn native_test() {
+    let (output, errors) = run_program_with(
+        "print clock() > 0;
+        print hypot(3, 4);
+        print hypot;",
+        |evaluator| {
+            evaluator.define_native("hypot", 2, |args| match args {
+                [a, b] => match (a.as_float(), b.as_float()) {
+                    (Some(a), Some(b)) => Ok(LoxType::Number(a.hypot(b))),
+                    _ => Err(LoxError::new_text_only(None, "hypot takes two numbers")),
+                },
+                _ => Err(LoxError::new_text_only(None, "hypot takes two numbers")),
+            })
+        },
+    );
+    assert_eq!(0, errors.len());
+    assert_eq!("true\n5\n<native fn hypot>\n", output);
+
+    // Errors from the native come back like any other runtime error
+    let (_, errors) = run_program_with("hypot(1, nil);", |evaluator| {
+        evaluator.define_native("hypot", 2, |_| {
+            Err(LoxError::new_text_only(None, "hypot takes two numbers"))
+        })
+    });
+    assert_eq!(1, errors.len());
+    let (_, errors) = run_program("clock(1);");
+    assert_eq!(1, errors.len());
+}
+
+#[test]
+pub fn interpolation_test() {
+    let (output, errors) = run_program(
+        "var name = \"world\";
+        print \"Hello ${name}!\";
+        print \"${1 + 2} is ${nil} or ${true}, not ${\"${name}s\"}\";
+        fun f() {}
+        print \"f is ${f}\";",
+    );
+    assert_eq!(0, errors.len());
+    assert_eq!(
+        "Hello world!\n3 is nil or true, not worlds\nf is <fn f>\n",
+        output
+    );
+
+    let (_, errors) = run_program("print \"${1 2}\";");
+    assert_ne!(0, errors.len());
+}
+
+#[test]
+pub fn integer_test() {
+    let (output, errors) = run_program(
+        "print 9007199254740993;
+        print 9007199254740992 + 1;
+        print 7 / 2;
+        print 7 ~/ 2;
+        print -7 ~/ 2;
+        print -7 % 3;
+        print 7 % -3;
+        print 7.5 % 2;
+        print 2 * 1.5;
+        print 1 == 1.0;
+        print 9007199254740993 == 9007199254740992.0;
+        print 9007199254740993 > 9007199254740992.0;
+        print 2 < 2.5;
+        print 0.0 / 0 == 0.0 / 0;",
+    );
+    assert_eq!(0, errors.len());
+    assert_eq!(
+        "9007199254740993\n9007199254740993\n3.5\n3\n-4\n2\n-2\n1.5\n3\ntrue\nfalse\ntrue\ntrue\nfalse\n",
+        output
+    );
+
+    let runtime_error = |program: &str| {
+        let (_, errors) = run_program(program);
+        assert_eq!(1, errors.len());
+        errors.iter().next().unwrap().report_msg()
+    };
+    assert_eq!(
+        "1: at '+' - Integer overflow.",
+        runtime_error("print 9223372036854775807 + 1;")
+    );
+    assert_eq!(
+        "1: at '*' - Integer overflow.",
+        runtime_error("print 0x4000_0000_0000_0000 * 2;")
+    );
+    assert_eq!(
+        "1: at '-' - Integer overflow.",
+        runtime_error("var a = -9223372036854775807 - 1; print -a;")
+    );
+    assert_eq!(
+        "1: at '~/' - Division by zero.",
+        runtime_error("print 1 ~/ 0;")
+    );
+    assert_eq!(
+        "1: at '%' - Division by zero.",
+        runtime_error("print 1 % 0;")
+    );
+    assert_eq!(
+        "1: at '%' - Expected number but found string",
+        runtime_error("print 1 % \"2\";")
+    );
+}
+
+#[test]
+pub fn operator_test() {
+    let (output, errors) = run_program(
+        "print 2 ** 10;
+        print 2 ** 3 ** 2;
+        print -2 ** 2;
+        print 2 ** -1;
+        print 4 ** 0.5;
+        print 6 & 3;
+        print 6 | 3;
+        print 6 ^ 3;
+        print ~5;
+        print 1 << 4;
+        print -16 >> 2;
+        print 1 | 2 == 3;
+        print 1 + 1 << 2;
+        print 1 ** 5000000000;
+        print 0 ** 5000000000;
+        print 0 ** 0;
+        print -1 ** 5000000000;
+        print (-1) ** 5000000001;",
+    );
+    assert_eq!(0, errors.len());
+    assert_eq!(
+        "1024\n512\n-4\n0.5\n2\n2\n7\n5\n-6\n16\n-4\ntrue\n8\n1\n0\n1\n-1\n-1\n",
+        output
+    );
+
+    let runtime_error = |program: &str| {
+        let (_, errors) = run_program(program);
+        assert_eq!(1, errors.len());
+        errors.iter().next().unwrap().report_msg()
+    };
+    assert_eq!(
+        "1: at '&' - Expected integer but found number",
+        runtime_error("print 1.5 & 1;")
+    );
+    assert_eq!(
+        "1: at '~' - Expected integer but found string",
+        runtime_error("print ~\"a\";")
+    );
+    assert_eq!(
+        "1: at '<<' - Shift amount must be between 0 and 63.",
+        runtime_error("print 1 << 64;")
+    );
+    assert_eq!(
+        "1: at '**' - Integer overflow.",
+        runtime_error("print 10 ** 19;")
+    );
+    assert_eq!(
+        "1: at '**' - Integer overflow.",
+        runtime_error("print 2 ** 5000000000;")
+    );
+}
+
+// Runs a program start to finish, handing back everything it printed along
+// with any errors it ran into along the way.
+#[cfg(test)]
+pub fn run_program(program: &str) -> (String, LoxErrorList) {
+    run_program_with(program, |_| ())
+}
+
+// Lets a test hang onto the buffer after the evaluator has taken its box
+#[cfg(test)]
+pub struct SharedBuffer(pub Rc<RefCell<Vec<u8>>>);
+
+#[cfg(test)]
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub fn run_program_with<F>(program: &str, setup: F) -> (String, LoxErrorList)
+where
+    F: FnOnce(&mut Evaluator) + Send,
+{
+    use crate::setup::compile::front_end;
+
+    let run = || {
+        let statements = match front_end(program) {
+            Ok(statements) => statements,
+            Err(errors) => return (String::new(), errors),
+        };
+
+        let buffer = Rc::new(RefCell::new(vec![]));
+        let mut evaluator = Evaluator::with_output(Box::new(SharedBuffer(buffer.clone())));
+        setup(&mut evaluator);
+        let errors = evaluator.interpret(&statements);
+        let output = String::from_utf8(buffer.borrow().clone()).unwrap();
+        (output, errors)
+    };
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, run)
+            .unwrap()
+            .join()
+            .unwrap()
+    })
+}
+
+#[test]
+pub fn block_scope_test() {
+    let (output, errors) = run_program(
+        "var a = \"global a\";
+        var b = \"global b\";
+        {
+            var a = \"outer a\";
+            {
+                var a = \"inner a\";
+                print a;
+                print b;
+                b = \"changed b\";
+            }
+            print a;
+        }
+        print a;
+        print b;",
+    );
+    assert_eq!(0, errors.len());
+    assert_eq!("inner a\nglobal b\nouter a\nglobal a\nchanged b\n", output);
+
+    // The runtime error inside the block mustn't leave us stuck in its scope
+    let mut evaluator = Evaluator::with_output(Box::new(std::io::sink()));
+    let program = "var a = 1; { var a = 2; -\"oops\"; }".to_string();
+    let mut scanner = crate::scanner::scanner::Scanner::new(&program);
+    scanner.scan_tokens();
+    let statements = crate::parser::parser::Parser::new(scanner.get_tokens())
+        .parse()
+        .unwrap();
+    assert_eq!(
+        0,
+        crate::parser::resolver::Resolver::new()
+            .resolve(&statements)
+            .len()
+    );
+    assert_eq!(1, evaluator.interpret(&statements).len());
+    let global =
+        evaluator
+            .environment
+            .borrow()
+            .get(&Token::new(&TokenType::Identifier("a".into()), "a", 1));
+    assert!(global.ok() == Some(LoxType::Integer(1)));
+}
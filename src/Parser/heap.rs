@@ -0,0 +1,340 @@
+use crate::parser;
+use crate::vm;
+
+use parser::callable::LoxFunction;
+use parser::class::{LoxClass, LoxInstance};
+use parser::environment::Environment;
+use parser::evaluate::LoxType;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+use vm::value::HeapStats;
+
+// Don't bother collecting until there are at least this many objects
+const MIN_NEXT_GC: usize = 1024;
+
+// The tree walker's values are reference counted which frees most things
+// as soon as they're finished with, but not cycles - an instance with a
+// field pointing back at itself or a closure stored in the environment it
+// captured.  So every environment, function, class and instance is made
+// through here and the heap keeps track of them to find those cycles.
+//
+// Roots are anything referred to from outside the heap: the evaluator's
+// globals and current environment and every value Rust code is holding
+// onto part way through evaluating something.  Rather than having all that
+// registered we work it out the way CPython does - an object with more
+// references than the other heap objects account for has to be referred to
+// from somewhere else.  We mark from those and whatever isn't reached can
+// only be alive because of a cycle.
+pub struct Heap {
+    objects: Vec<Tracked>,
+    next_gc: usize,
+    // Collect on every allocation to shake out anything the collector gets
+    // wrong
+    stress_gc: bool,
+    stats: HeapStats,
+}
+
+enum Tracked {
+    Environment(Weak<RefCell<Environment>>),
+    Function(Weak<LoxFunction>),
+    Class(Weak<LoxClass>),
+    Instance(Weak<RefCell<LoxInstance>>),
+}
+
+// A tracked object we're holding on to while collecting
+enum Object {
+    Environment(Rc<RefCell<Environment>>),
+    Function(Rc<LoxFunction>),
+    Class(Rc<LoxClass>),
+    Instance(Rc<RefCell<LoxInstance>>),
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Heap {
+            objects: vec![],
+            next_gc: MIN_NEXT_GC,
+            stress_gc: false,
+            stats: HeapStats::default(),
+        }
+    }
+
+    pub fn set_stress_gc(&mut self, stress_gc: bool) {
+        self.stress_gc = stress_gc;
+    }
+
+    // Objects freed by their reference count going to zero are only
+    // noticed at the next collection so they count as live until then
+    pub fn stats(&self) -> HeapStats {
+        self.stats
+    }
+
+    pub fn environment(&mut self, environment: Environment) -> Rc<RefCell<Environment>> {
+        let environment = Rc::new(RefCell::new(environment));
+        self.track(Tracked::Environment(Rc::downgrade(&environment)));
+        environment
+    }
+
+    pub fn function(&mut self, function: LoxFunction) -> Rc<LoxFunction> {
+        let function = Rc::new(function);
+        self.track(Tracked::Function(Rc::downgrade(&function)));
+        function
+    }
+
+    pub fn class(&mut self, class: LoxClass) -> Rc<LoxClass> {
+        let class = Rc::new(class);
+        self.track(Tracked::Class(Rc::downgrade(&class)));
+        class
+    }
+
+    pub fn instance(&mut self, instance: LoxInstance) -> Rc<RefCell<LoxInstance>> {
+        let instance = Rc::new(RefCell::new(instance));
+        self.track(Tracked::Instance(Rc::downgrade(&instance)));
+        instance
+    }
+
+    // The heap doubles between collections so the work done collecting
+    // stays in proportion to the work done allocating.  The new object is
+    // held by our caller so it's safe from the collection.
+    fn track(&mut self, object: Tracked) {
+        if self.stress_gc || self.objects.len() >= self.next_gc {
+            self.collect();
+        }
+        self.objects.push(object);
+        self.stats.allocated += 1;
+        self.stats.live = self.objects.len();
+        self.stats.peak = self.stats.peak.max(self.stats.live);
+    }
+
+    pub fn collect(&mut self) {
+        let before = self.objects.len();
+        // Upgrading holds one extra reference to each object until we're done
+        let objects: Vec<Object> = self.objects.iter().filter_map(Tracked::upgrade).collect();
+        let index: HashMap<usize, usize> = objects
+            .iter()
+            .enumerate()
+            .map(|(i, object)| (object.address(), i))
+            .collect();
+
+        let mut references = vec![vec![]; objects.len()];
+        let mut held = vec![0; objects.len()];
+        let mut marked = vec![false; objects.len()];
+        let mut gray = vec![];
+        for (i, object) in objects.iter().enumerate() {
+            match object.references() {
+                Some(found) => {
+                    for address in &found {
+                        if let Some(&j) = index.get(address) {
+                            held[j] += 1;
+                        }
+                    }
+                    references[i] = found;
+                }
+                // Something is part way through changing it so it's in use.
+                // What it refers to didn't get counted so will look like
+                // roots too.
+                None => {
+                    marked[i] = true;
+                    gray.push(i);
+                }
+            }
+        }
+
+        for (i, object) in objects.iter().enumerate() {
+            if !marked[i] && object.strong_count() - 1 > held[i] {
+                marked[i] = true;
+                gray.push(i);
+            }
+        }
+        while let Some(i) = gray.pop() {
+            for address in &references[i] {
+                if let Some(&j) = index.get(address) {
+                    if !marked[j] {
+                        marked[j] = true;
+                        gray.push(j);
+                    }
+                }
+            }
+        }
+
+        // Functions and classes can't be changed once they're made so every
+        // cycle goes through an environment or an instance.  Emptying the
+        // unreachable ones breaks the cycles and dropping our references
+        // lets the reference counts free the lot.
+        for (i, object) in objects.iter().enumerate() {
+            if !marked[i] {
+                object.clear();
+            }
+        }
+        drop(objects);
+
+        self.objects.retain(Tracked::is_alive);
+        self.stats.freed += before - self.objects.len();
+        self.stats.live = self.objects.len();
+        self.stats.collections += 1;
+        self.next_gc = MIN_NEXT_GC.max(self.stats.live * 2);
+    }
+}
+
+// Where an object lives which is how we tell them apart while collecting
+pub fn address<T: ?Sized>(rc: &Rc<T>) -> usize {
+    Rc::as_ptr(rc) as *const u8 as usize
+}
+
+// Adds the object a value refers to, if it's one the heap tracks
+pub fn value_reference(value: &LoxType, found: &mut Vec<usize>) {
+    match value {
+        LoxType::Function(function) => found.push(address(function)),
+        LoxType::Class(class) => found.push(address(class)),
+        LoxType::Instance(instance) => found.push(address(instance)),
+        _ => (),
+    }
+}
+
+impl Tracked {
+    fn upgrade(&self) -> Option<Object> {
+        match self {
+            Tracked::Environment(weak) => weak.upgrade().map(Object::Environment),
+            Tracked::Function(weak) => weak.upgrade().map(Object::Function),
+            Tracked::Class(weak) => weak.upgrade().map(Object::Class),
+            Tracked::Instance(weak) => weak.upgrade().map(Object::Instance),
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        match self {
+            Tracked::Environment(weak) => weak.strong_count() > 0,
+            Tracked::Function(weak) => weak.strong_count() > 0,
+            Tracked::Class(weak) => weak.strong_count() > 0,
+            Tracked::Instance(weak) => weak.strong_count() > 0,
+        }
+    }
+}
+
+impl Object {
+    fn address(&self) -> usize {
+        match self {
+            Object::Environment(rc) => address(rc),
+            Object::Function(rc) => address(rc),
+            Object::Class(rc) => address(rc),
+            Object::Instance(rc) => address(rc),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Object::Environment(rc) => Rc::strong_count(rc),
+            Object::Function(rc) => Rc::strong_count(rc),
+            Object::Class(rc) => Rc::strong_count(rc),
+            Object::Instance(rc) => Rc::strong_count(rc),
+        }
+    }
+
+    // Every reference the object holds to another, once for each time it
+    // holds it.  None if it can't be looked at right now.
+    fn references(&self) -> Option<Vec<usize>> {
+        let mut found = vec![];
+        match self {
+            Object::Environment(rc) => rc.try_borrow().ok()?.references(&mut found),
+            Object::Function(rc) => rc.references(&mut found),
+            Object::Class(rc) => rc.references(&mut found),
+            Object::Instance(rc) => rc.try_borrow().ok()?.references(&mut found),
+        }
+        Some(found)
+    }
+
+    fn clear(&self) {
+        match self {
+            Object::Environment(rc) => rc.borrow_mut().clear(),
+            Object::Instance(rc) => rc.borrow_mut().clear(),
+            Object::Function(_) | Object::Class(_) => (),
+        }
+    }
+}
+
+#[cfg(test)]
+use crate::lox_error::lox_error::LoxErrorList;
+#[cfg(test)]
+use crate::parser::evaluate::{run_program, run_program_with, Evaluator, SharedBuffer};
+#[cfg(test)]
+use crate::setup::compile::front_end;
+
+#[test]
+pub fn evaluator_gc_test() {
+    // The same cycles as the VM's gc_test - instances pointing at themselves
+    // and closures stored in the environment they captured
+    let program = "
+        class Node { init(next) { this.next = next; this.me = this; } }
+        var keep = Node(nil);
+        fun churn(n) {
+            var last = nil;
+            for (var i = 0; i < n; i = i + 1) {
+                var node = Node(nil);
+                fun cycle() { return node; }
+                node.cycle = cycle;
+                last = Node(last);
+                if (i % 100 == 0) last = nil;
+            }
+            return last;
+        }
+        keep.next = churn(3000);
+        print keep.me == keep;
+        print keep.next.next.me == keep.next.next;
+        print \"${keep.next == nil} done\";";
+
+    let statements = front_end(program).ok().unwrap();
+    let buffer = Rc::new(RefCell::new(vec![]));
+    let mut evaluator = Evaluator::with_output(Box::new(SharedBuffer(buffer.clone())));
+    assert_eq!(0, evaluator.interpret(&statements).len());
+    let output = String::from_utf8(buffer.borrow().clone()).unwrap();
+    assert_eq!("true\ntrue\nfalse done\n", output);
+
+    evaluator.heap().collect();
+    let stats = evaluator.heap_stats();
+    assert!(stats.collections > 1);
+    assert!(stats.freed > 10_000);
+    // Only the last hundred or so nodes are still reachable
+    assert!(stats.live < 1024, "{:?}", stats);
+    assert_eq!(stats.allocated, stats.live + stats.freed);
+}
+
+// Collecting on every allocation mustn't change what any program does
+#[test]
+pub fn evaluator_stress_gc_test() {
+    let programs = [
+        "fun counter() { var n = 0; fun inc() { n = n + 1; return n; } return inc; }
+        var a = counter(); var b = counter(); a(); a(); b(); print a(); print b();",
+        "class A { init(x) { this.x = x; } get() { return this.x; } }
+        class B < A { init(x) { super.init(x * 2); } get() { return super.get() + 1; } }
+        var b = B(5); var get = b.get; b = nil; print get();",
+        "class Node { init() { this.me = this; } }
+        for (var i = 0; i < 50; i = i + 1) { var n = Node(); n.f = fun_of(n); }
+        fun fun_of(n) { fun f() { return n; } return f; }",
+        "class Node { init() { this.me = this; } }
+        fun fun_of(n) { fun f() { return n; } return f; }
+        var keep;
+        for (var i = 0; i < 50; i = i + 1) { var n = Node(); n.f = fun_of(n); keep = n; }
+        print keep.f().me == keep;",
+        "var fs = nil;
+        for (var i = 0; i < 3; i = i + 1) { var j = i; fun f() { print j; } if (i == 1) fs = f; }
+        fs();",
+        "class A { method() { print \"${this} method\"; } } A().method(); print A;",
+        "fun f(n) { if (n > 0) return f(n - 1); return \"done\"; } print f(50);",
+        "class A {} var a = A(); a.x = 1; print a.y;",
+    ];
+    for program in programs.iter() {
+        let expected = run_program(program);
+        let stressed = run_program_with(program, |evaluator| evaluator.set_stress_gc(true));
+        assert_eq!(expected.0, stressed.0, "output of {}", program);
+        let messages = |errors: &LoxErrorList| -> Vec<String> {
+            errors.iter().map(|error| error.report_msg()).collect()
+        };
+        assert_eq!(
+            messages(&expected.1),
+            messages(&stressed.1),
+            "errors of {}",
+            program
+        );
+    }
+}
@@ -1,5 +1,10 @@
+pub mod callable;
+pub mod class;
+pub mod environment;
 pub mod evaluate;
+pub mod heap;
 pub mod parser;
 pub mod pretty_print;
+pub mod resolver;
 pub mod statement;
 pub mod struct_macros;
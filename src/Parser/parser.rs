@@ -4,12 +4,24 @@ use crate::scanner;
 
 use lox_error::lox_error::{LoxError, LoxErrorList};
 use parser::evaluate;
-use scanner::{token::Token, token_type::TokenType};
+use parser::statement::sstructs;
+use scanner::{
+    token::{Span, Token},
+    token_type::TokenType,
+};
+use std::cell::Cell;
+use std::rc::Rc;
+
+// The most arguments (or parameters) a call can have
+const MAX_ARGS: usize = 255;
 
 // An AST always owns the entire tree below it so when the AST goes
 // out of scope the entire tree is destroyed
 type AST = Box<dyn pstructs::Accept + 'static>;
 
+// Statements are owned the same way.  A program is just a list of these.
+pub type Stmt = Box<dyn sstructs::Accept + 'static>;
+
 // ParseReturn is an enumeration to allow us to use Accept without generic
 // parameters which in turn would keep cause rustc to disallow dyn Accept.  Instead of
 // using a generic parameter to indicate our return type we always return
@@ -20,6 +32,11 @@ type AST = Box<dyn pstructs::Accept + 'static>;
 pub enum ParseReturn {
     PP(String),
     Val(evaluate::LoxType),
+    // Statements don't produce values so they return Unit
+    Unit,
+    // Unless they're a return statement in which case this carries the value
+    // back up through any enclosing statements to the function call
+    Return(evaluate::LoxType),
 }
 
 // Putting these in their own module because we're gonna need more build_structs
@@ -31,14 +48,29 @@ pub mod pstructs {
     use crate::{build_struct, build_structs, exprType};
 
     build_structs! {
+        assign : Token name, expr value, scope_depth depth;
         binary : expr left, Token operator, expr right;
+        call : expr callee, Token paren, exprs arguments;
+        get : expr object, Token name;
         grouping : expr expression;
+        interpolation : exprs parts;
         literal : TokenType value;
+        logical : expr left, Token operator, expr right;
+        set : expr object, Token name, expr value;
+        super_expr : Token keyword, Token method, scope_depth depth;
+        this : Token keyword, scope_depth depth;
         unary : Token operator, expr right;
+        variable : Token name, scope_depth depth;
     }
 
     pub trait Accept {
         fn accept(&self, visitor: &mut dyn Visitor) -> Result<ParseReturn, LoxError>;
+        fn span(&self) -> crate::scanner::token::Span;
+        // Lets the parser find out what sort of expression it's holding
+        // when it has to decide if something is a valid assignment target
+        fn as_any(&self) -> &dyn std::any::Any;
+        // ...and take it apart once it's decided
+        fn into_any(self: Box<Self>) -> Box<dyn std::any::Any>;
     }
 }
 
@@ -46,13 +78,21 @@ pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
     pub errors: LoxErrorList,
+    // Set from the first error until we've synchronized.  Errors in between
+    // are almost certainly fallout from the first one so they're dropped.
+    panic_mode: bool,
+    // How many blocks and class bodies we're inside.  A '}' is only worth
+    // stopping at when synchronizing if there's something for it to close.
+    nesting: usize,
 }
 
 macro_rules! match_one_of {
     ($parser: ident, $($ttype:expr),*) => (
         {
+            // Only the first match is consumed - otherwise 1 "a" would
+            // swallow both of them as one literal
             let mut ret = false;
-            $(if $parser.check ($ttype) {
+            $(if !ret && $parser.check ($ttype) {
                 $parser.advance();
                 ret = true;
             })*
@@ -63,24 +103,358 @@ macro_rules! match_one_of {
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
+        // Comments are only there for tools that want them - the grammar
+        // doesn't know about them
+        let tokens = tokens
+            .into_iter()
+            .filter(|token| !matches!(token.ttype, TokenType::Comment(_)))
+            .collect();
         Parser {
             tokens,
             current: 0,
             errors: LoxErrorList::new(),
+            panic_mode: false,
+            nesting: 0,
         }
     }
 
-    pub fn parse(&mut self) -> Option<AST> {
-        let result = self.expression();
+    pub fn parse(&mut self) -> Option<Vec<Stmt>> {
+        let mut statements = vec![];
+        while !self.is_at_end() {
+            statements.push(self.declaration());
+        }
+
         if self.errors.len() == 0 {
-            Some(result)
+            Some(statements)
+        } else {
+            None
+        }
+    }
+
+    // Declarations are where we recover from errors.  Whatever got built for
+    // a broken declaration is junk but it never gets run - parse() won't hand
+    // back any statements once there's been an error.
+    fn declaration(&mut self) -> Stmt {
+        let start = self.current;
+        let stmt = if match_one_of!(self, &TokenType::Class) {
+            self.class_declaration()
+        } else if match_one_of!(self, &TokenType::Fun) {
+            Box::new(self.function("function"))
+        } else if match_one_of!(self, &TokenType::Var) {
+            self.var_declaration()
+        } else {
+            self.statement()
+        };
+
+        if self.panic_mode {
+            self.synchronize(start);
+        }
+        stmt
+    }
+
+    fn class_declaration(&mut self) -> Stmt {
+        let start = self.previous().span();
+        let name = self.peek().clone();
+        self.consume(TokenType::Identifier("".into()), "Expect class name.");
+
+        let superclass = if match_one_of!(self, &TokenType::Less) {
+            let superclass_name = self.peek().clone();
+            self.consume(TokenType::Identifier("".into()), "Expect superclass name.");
+            let span = superclass_name.span();
+            Some(pstructs::variable::new(
+                superclass_name,
+                Cell::new(None),
+                span,
+            ))
+        } else {
+            None
+        };
+
+        self.consume(TokenType::LeftBrace, "Expect '{' before class body.");
+
+        let mut methods = vec![];
+        self.nesting += 1;
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            methods.push(self.function("method"));
+            if self.panic_mode {
+                break;
+            }
+        }
+
+        self.nesting -= 1;
+        self.consume(TokenType::RightBrace, "Expect '}' after class body.");
+        Box::new(sstructs::class::new(
+            name,
+            superclass,
+            methods,
+            self.span_from(start),
+        ))
+    }
+
+    // kind is just for error messages so we can say what we were expecting.
+    // Classes need the function itself rather than a Stmt so that's what we
+    // hand back.
+    fn function(&mut self, kind: &str) -> sstructs::function {
+        let name = self.peek().clone();
+        let start = name.span();
+        self.consume(
+            TokenType::Identifier("".into()),
+            &format!("Expect {} name.", kind),
+        );
+
+        self.consume(
+            TokenType::LeftParen,
+            &format!("Expect '(' after {} name.", kind),
+        );
+        let mut params = vec![];
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                if params.len() >= MAX_ARGS {
+                    let token = self.peek().clone();
+                    self.err_on_token(&token, "Can't have more than 255 parameters.");
+                }
+                params.push(self.peek().clone());
+                self.consume(TokenType::Identifier("".into()), "Expect parameter name.");
+                if !match_one_of!(self, &TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after parameters.");
+
+        self.consume(
+            TokenType::LeftBrace,
+            &format!("Expect '{{' before {} body.", kind),
+        );
+        let body = self.block();
+        sstructs::function::new(name, params, Rc::new(body), self.span_from(start))
+    }
+
+    fn var_declaration(&mut self) -> Stmt {
+        let start = self.previous().span();
+        let name = self.peek().clone();
+        self.consume(TokenType::Identifier("".into()), "Expect variable name.");
+
+        let initializer = if match_one_of!(self, &TokenType::Equal) {
+            Some(self.expression())
+        } else {
+            None
+        };
+
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after variable declaration.",
+        );
+        Box::new(sstructs::var::new(name, initializer, self.span_from(start)))
+    }
+
+    fn statement(&mut self) -> Stmt {
+        if match_one_of!(self, &TokenType::For) {
+            self.for_statement()
+        } else if match_one_of!(self, &TokenType::If) {
+            self.if_statement()
+        } else if match_one_of!(self, &TokenType::Print) {
+            self.print_statement()
+        } else if match_one_of!(self, &TokenType::Return) {
+            self.return_statement()
+        } else if match_one_of!(self, &TokenType::While) {
+            self.while_statement()
+        } else if match_one_of!(self, &TokenType::LeftBrace) {
+            let start = self.previous().span();
+            let statements = self.block();
+            Box::new(sstructs::block::new(statements, self.span_from(start)))
+        } else {
+            self.expression_statement()
+        }
+    }
+
+    // Assumes the opening brace has already been consumed
+    fn block(&mut self) -> Vec<Stmt> {
+        let mut statements = vec![];
+
+        self.nesting += 1;
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            statements.push(self.declaration());
+        }
+        self.nesting -= 1;
+
+        self.consume(TokenType::RightBrace, "Expect '}' after block.");
+        statements
+    }
+
+    // There's no for node - we just build the equivalent while loop
+    //     for (init; cond; incr) body
+    // becomes
+    //     { init; while (cond) { body; incr; } }
+    fn for_statement(&mut self) -> Stmt {
+        let start = self.previous().span();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'.");
+
+        let initializer = if match_one_of!(self, &TokenType::Semicolon) {
+            None
+        } else if match_one_of!(self, &TokenType::Var) {
+            Some(self.var_declaration())
+        } else {
+            Some(self.expression_statement())
+        };
+
+        let condition = if self.check(&TokenType::Semicolon) {
+            None
         } else {
+            Some(self.expression())
+        };
+        self.consume(TokenType::Semicolon, "Expect ';' after loop condition.");
+
+        let increment = if self.check(&TokenType::RightParen) {
             None
+        } else {
+            Some(self.expression())
+        };
+        self.consume(TokenType::RightParen, "Expect ')' after for clauses.");
+
+        let mut body = self.statement();
+
+        // All the made up nodes just claim the whole for statement
+        let span = self.span_from(start);
+        if let Some(increment) = increment {
+            let increment_span = increment.span();
+            body = Box::new(sstructs::block::new(
+                vec![
+                    body,
+                    Box::new(sstructs::expression::new(increment, increment_span)),
+                ],
+                span,
+            ));
         }
+
+        let condition =
+            condition.unwrap_or_else(|| Box::new(pstructs::literal::new(TokenType::True, span)));
+        body = Box::new(sstructs::while_stmt::new(condition, body, span));
+
+        if let Some(initializer) = initializer {
+            body = Box::new(sstructs::block::new(vec![initializer, body], span));
+        }
+        body
+    }
+
+    fn if_statement(&mut self) -> Stmt {
+        let start = self.previous().span();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.");
+        let condition = self.expression();
+        self.consume(TokenType::RightParen, "Expect ')' after if condition.");
+
+        // Any else belongs to the nearest if so there's no dangling else problem
+        let then_branch = self.statement();
+        let else_branch = if match_one_of!(self, &TokenType::Else) {
+            Some(self.statement())
+        } else {
+            None
+        };
+
+        Box::new(sstructs::if_stmt::new(
+            condition,
+            then_branch,
+            else_branch,
+            self.span_from(start),
+        ))
+    }
+
+    fn while_statement(&mut self) -> Stmt {
+        let start = self.previous().span();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.");
+        let condition = self.expression();
+        self.consume(TokenType::RightParen, "Expect ')' after condition.");
+        let body = self.statement();
+
+        Box::new(sstructs::while_stmt::new(
+            condition,
+            body,
+            self.span_from(start),
+        ))
+    }
+
+    fn print_statement(&mut self) -> Stmt {
+        let start = self.previous().span();
+        let value = self.expression();
+        self.consume(TokenType::Semicolon, "Expect ';' after value.");
+        Box::new(sstructs::print::new(value, self.span_from(start)))
+    }
+
+    fn return_statement(&mut self) -> Stmt {
+        let keyword = self.previous().clone();
+        let value = if self.check(&TokenType::Semicolon) {
+            None
+        } else {
+            Some(self.expression())
+        };
+
+        self.consume(TokenType::Semicolon, "Expect ';' after return value.");
+        let span = self.span_from(keyword.span());
+        Box::new(sstructs::return_stmt::new(keyword, value, span))
+    }
+
+    fn expression_statement(&mut self) -> Stmt {
+        let expr = self.expression();
+        let start = expr.span();
+        self.consume(TokenType::Semicolon, "Expect ';' after expression.");
+        Box::new(sstructs::expression::new(expr, self.span_from(start)))
     }
 
     fn expression(&mut self) -> AST {
-        self.equality()
+        self.assignment()
+    }
+
+    fn assignment(&mut self) -> AST {
+        let expr = self.or();
+
+        if match_one_of!(self, &TokenType::Equal) {
+            let equals = self.previous().clone();
+            let value = self.assignment();
+
+            let span = expr.span().to(value.span());
+            if let Some(var) = expr.as_any().downcast_ref::<pstructs::variable>() {
+                return Box::new(pstructs::assign::new(
+                    var.name.clone(),
+                    value,
+                    Cell::new(None),
+                    span,
+                ));
+            }
+
+            // A get on the left hand side turns into a set on the same object
+            if expr.as_any().is::<pstructs::get>() {
+                let get = expr.into_any().downcast::<pstructs::get>().ok().unwrap();
+                return Box::new(pstructs::set::new(get.object, get.name, value, span));
+            }
+
+            // Not worth bailing out over - we just ignore the assignment
+            self.err_on_token(&equals, "Invalid assignment target.");
+        }
+        expr
+    }
+
+    fn or(&mut self) -> AST {
+        let mut expr = self.and();
+
+        while match_one_of!(self, &TokenType::Or) {
+            let operator = self.previous().clone();
+            let right = self.and();
+            let span = expr.span().to(right.span());
+            expr = Box::new(pstructs::logical::new(expr, operator, right, span));
+        }
+        expr
+    }
+
+    fn and(&mut self) -> AST {
+        let mut expr = self.equality();
+
+        while match_one_of!(self, &TokenType::And) {
+            let operator = self.previous().clone();
+            let right = self.equality();
+            let span = expr.span().to(right.span());
+            expr = Box::new(pstructs::logical::new(expr, operator, right, span));
+        }
+        expr
     }
 
     fn equality(&mut self) -> AST {
@@ -89,13 +463,14 @@ impl Parser {
         while match_one_of!(self, &TokenType::BangEqual, &TokenType::EqualEqual) {
             let operator = self.previous().clone();
             let right = self.comparison();
-            expr = Box::new(pstructs::binary::new(expr, operator, right));
+            let span = expr.span().to(right.span());
+            expr = Box::new(pstructs::binary::new(expr, operator, right, span));
         }
         expr
     }
 
     fn comparison(&mut self) -> AST {
-        let mut expr = self.term();
+        let mut expr = self.bit_or();
 
         while match_one_of!(
             self,
@@ -104,9 +479,60 @@ impl Parser {
             &TokenType::Less,
             &TokenType::LessEqual
         ) {
+            let operator = self.previous().clone();
+            let right = self.bit_or();
+            let span = expr.span().to(right.span());
+            expr = Box::new(pstructs::binary::new(expr, operator, right, span));
+        }
+        expr
+    }
+
+    // The bitwise operators go between comparison and term the way Python
+    // has them so "a & mask == 0" means what it looks like it means
+    fn bit_or(&mut self) -> AST {
+        let mut expr = self.bit_xor();
+
+        while match_one_of!(self, &TokenType::Pipe) {
+            let operator = self.previous().clone();
+            let right = self.bit_xor();
+            let span = expr.span().to(right.span());
+            expr = Box::new(pstructs::binary::new(expr, operator, right, span));
+        }
+        expr
+    }
+
+    fn bit_xor(&mut self) -> AST {
+        let mut expr = self.bit_and();
+
+        while match_one_of!(self, &TokenType::Caret) {
+            let operator = self.previous().clone();
+            let right = self.bit_and();
+            let span = expr.span().to(right.span());
+            expr = Box::new(pstructs::binary::new(expr, operator, right, span));
+        }
+        expr
+    }
+
+    fn bit_and(&mut self) -> AST {
+        let mut expr = self.shift();
+
+        while match_one_of!(self, &TokenType::Ampersand) {
+            let operator = self.previous().clone();
+            let right = self.shift();
+            let span = expr.span().to(right.span());
+            expr = Box::new(pstructs::binary::new(expr, operator, right, span));
+        }
+        expr
+    }
+
+    fn shift(&mut self) -> AST {
+        let mut expr = self.term();
+
+        while match_one_of!(self, &TokenType::LessLess, &TokenType::GreaterGreater) {
             let operator = self.previous().clone();
             let right = self.term();
-            expr = Box::new(pstructs::binary::new(expr, operator, right));
+            let span = expr.span().to(right.span());
+            expr = Box::new(pstructs::binary::new(expr, operator, right, span));
         }
         expr
     }
@@ -117,7 +543,8 @@ impl Parser {
         while match_one_of!(self, &TokenType::Minus, &TokenType::Plus) {
             let operator = self.previous().clone();
             let right = self.factor();
-            expr = Box::new(pstructs::binary::new(expr, operator, right));
+            let span = expr.span().to(right.span());
+            expr = Box::new(pstructs::binary::new(expr, operator, right, span));
         }
         expr
     }
@@ -125,22 +552,88 @@ impl Parser {
     fn factor(&mut self) -> AST {
         let mut expr = self.unary();
 
-        while match_one_of!(self, &TokenType::Slash, &TokenType::Star) {
+        while match_one_of!(
+            self,
+            &TokenType::Slash,
+            &TokenType::TildeSlash,
+            &TokenType::Star,
+            &TokenType::Percent
+        ) {
             let operator = self.previous().clone();
             let right = self.unary();
-            expr = Box::new(pstructs::binary::new(expr, operator, right));
+            let span = expr.span().to(right.span());
+            expr = Box::new(pstructs::binary::new(expr, operator, right, span));
         }
         expr
     }
 
     fn unary(&mut self) -> AST {
-        if match_one_of!(self, &TokenType::Bang, &TokenType::Minus) {
+        if match_one_of!(self, &TokenType::Bang, &TokenType::Minus, &TokenType::Tilde) {
             let operator = self.previous().clone();
             let right = self.unary();
-            Box::new(pstructs::unary::new(operator, right))
+            let span = operator.span().to(right.span());
+            Box::new(pstructs::unary::new(operator, right, span))
         } else {
-            self.primary()
+            self.power()
+        }
+    }
+
+    // ** binds tighter than a unary operator on its left so -2 ** 2 is -4,
+    // and it's right associative so 2 ** 3 ** 2 is 2 ** 9.  The right side
+    // goes back through unary() to allow 2 ** -1.
+    fn power(&mut self) -> AST {
+        let expr = self.call();
+
+        if match_one_of!(self, &TokenType::StarStar) {
+            let operator = self.previous().clone();
+            let right = self.unary();
+            let span = expr.span().to(right.span());
+            return Box::new(pstructs::binary::new(expr, operator, right, span));
+        }
+        expr
+    }
+
+    fn call(&mut self) -> AST {
+        let mut expr = self.primary();
+
+        loop {
+            if match_one_of!(self, &TokenType::LeftParen) {
+                expr = self.finish_call(expr);
+            } else if match_one_of!(self, &TokenType::Dot) {
+                let name = self.peek().clone();
+                self.consume(
+                    TokenType::Identifier("".into()),
+                    "Expect property name after '.'.",
+                );
+                let span = expr.span().to(self.previous().span());
+                expr = Box::new(pstructs::get::new(expr, name, span));
+            } else {
+                break;
+            }
+        }
+        expr
+    }
+
+    fn finish_call(&mut self, callee: AST) -> AST {
+        let mut arguments = vec![];
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                // Report it but keep parsing - the parser isn't confused
+                if arguments.len() >= MAX_ARGS {
+                    let token = self.peek().clone();
+                    self.err_on_token(&token, "Can't have more than 255 arguments.");
+                }
+                arguments.push(self.expression());
+                if !match_one_of!(self, &TokenType::Comma) {
+                    break;
+                }
+            }
         }
+
+        let paren = self.peek().clone();
+        self.consume(TokenType::RightParen, "Expect ')' after arguments.");
+        let span = self.span_from(callee.span());
+        Box::new(pstructs::call::new(callee, paren, arguments, span))
     }
 
     fn primary(&mut self) -> AST {
@@ -150,20 +643,87 @@ impl Parser {
             &TokenType::True,
             &TokenType::Nil,
             &TokenType::Number("".to_string()),
-            &TokenType::String("".to_string())
+            &TokenType::String("".into())
         ) {
-            return Box::new(pstructs::literal::new(self.previous().ttype.clone()));
+            let previous = self.previous();
+            return Box::new(pstructs::literal::new(
+                previous.ttype.clone(),
+                previous.span(),
+            ));
+        }
+
+        if match_one_of!(self, &TokenType::Interpolation("".to_string())) {
+            return self.interpolation();
+        }
+
+        if match_one_of!(self, &TokenType::Super) {
+            let keyword = self.previous().clone();
+            self.consume(TokenType::Dot, "Expect '.' after 'super'.");
+            let method = self.peek().clone();
+            self.consume(
+                TokenType::Identifier("".into()),
+                "Expect superclass method name.",
+            );
+            let span = self.span_from(keyword.span());
+            return Box::new(pstructs::super_expr::new(
+                keyword,
+                method,
+                Cell::new(None),
+                span,
+            ));
+        }
+
+        if match_one_of!(self, &TokenType::This) {
+            let keyword = self.previous().clone();
+            let span = keyword.span();
+            return Box::new(pstructs::this::new(keyword, Cell::new(None), span));
+        }
+
+        if match_one_of!(self, &TokenType::Identifier("".into())) {
+            let name = self.previous().clone();
+            let span = name.span();
+            return Box::new(pstructs::variable::new(name, Cell::new(None), span));
         }
 
         if match_one_of!(self, &TokenType::LeftParen) {
+            let start = self.previous().span();
             let expr = self.expression();
             self.consume(TokenType::RightParen, "Expect ')' after expression.");
-            return Box::new(pstructs::grouping::new(expr));
+            return Box::new(pstructs::grouping::new(expr, self.span_from(start)));
         }
 
-        self.errors
-            .push(LoxError::new(self.peek().clone(), "Invalid Token"));
-        Box::new(pstructs::literal::new(TokenType::Eof))
+        let token = self.peek().clone();
+        self.error(&token, "Invalid Token");
+        Box::new(pstructs::literal::new(TokenType::Eof, self.peek().span()))
+    }
+
+    // The scanner has already chopped the string up for us so this is just
+    // alternating literal parts and expressions until the closing String
+    fn interpolation(&mut self) -> AST {
+        let start = self.previous().span();
+        let mut parts: Vec<AST> = vec![];
+        loop {
+            let previous = self.previous();
+            if let TokenType::Interpolation(s) = &previous.ttype {
+                let text = TokenType::String(s.as_str().into());
+                parts.push(Box::new(pstructs::literal::new(text, previous.span())));
+            }
+            parts.push(self.expression());
+
+            if match_one_of!(self, &TokenType::Interpolation("".to_string())) {
+                continue;
+            }
+            if match_one_of!(self, &TokenType::String("".into())) {
+                let previous = self.previous();
+                let text = previous.ttype.clone();
+                parts.push(Box::new(pstructs::literal::new(text, previous.span())));
+            } else {
+                let token = self.peek().clone();
+                self.err_on_token(&token, "Expect end of string interpolation.");
+            }
+            break;
+        }
+        Box::new(pstructs::interpolation::new(parts, self.span_from(start)))
     }
 
     fn check(&self, tt: &TokenType) -> bool {
@@ -178,16 +738,31 @@ impl Parser {
         if self.check(&tt) {
             self.advance().unwrap().ttype
         } else {
-            // Advance or don't advance?  Book throws.
-            self.errors
-                .push(LoxError::new_text_only(Some(self.peek().line), msg));
+            // We don't advance - synchronize() decides how much to throw away
+            let token = self.peek().clone();
+            self.error(&token, msg);
             TokenType::Error
         }
     }
 
-    #[allow(unused)]
+    // Everything from start up to and including the last token we consumed
+    fn span_from(&self, start: Span) -> Span {
+        start.to(self.previous().span())
+    }
+
+    // For errors that leave the parser knowing exactly where it is - we
+    // report them and carry on as normal
     fn err_on_token(&mut self, token: &Token, msg: &str) {
-        self.errors.push(LoxError::new(token.clone(), msg))
+        if !self.panic_mode {
+            self.errors.push(LoxError::new(token.clone(), msg))
+        }
+    }
+
+    // For errors that leave us lost.  We report it and then stay quiet until
+    // the next declaration boundary.
+    fn error(&mut self, token: &Token, msg: &str) {
+        self.err_on_token(token, msg);
+        self.panic_mode = true;
     }
 
     fn is_at_end(&self) -> bool {
@@ -211,29 +786,101 @@ impl Parser {
         }
     }
 
-    // Synchronize the parser after an error
-    #[allow(unused)]
-    fn synchronize(&mut self) {
-        self.advance();
+    // Throw away tokens until we're somewhere a new declaration could
+    // plausibly start - just past a ';', in front of a keyword that begins a
+    // statement or in front of a '}' that can close an enclosing block.
+    // start is where the broken declaration began.  If we haven't moved from
+    // there we skip a token so we can't get stuck on it forever.
+    fn synchronize(&mut self, start: usize) {
+        self.panic_mode = false;
+        if self.current == start {
+            self.advance();
+        }
 
-        while (!self.is_at_end()) {
-            if (self.previous().ttype == TokenType::Semicolon) {
+        while !self.is_at_end() {
+            if self.previous().ttype == TokenType::Semicolon {
                 return;
             }
 
-            let tt = &self.peek().ttype;
-            if (*tt == TokenType::Class
-                || *tt == TokenType::Fun
-                || *tt == TokenType::Var
-                || *tt == TokenType::For
-                || *tt == TokenType::If
-                || *tt == TokenType::While
-                || *tt == TokenType::Print
-                || *tt == TokenType::Return)
-            {
-                return;
+            match self.peek().ttype {
+                TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return => return,
+                TokenType::RightBrace if self.nesting > 0 => return,
+                _ => {}
             }
             self.advance();
         }
     }
 }
+
+#[test]
+pub fn parse_statements_test() {
+    use crate::scanner::scanner::Scanner;
+
+    let program = "print 1 + 2;\n\"a\" + \"b\";\nprint nil;".to_string();
+    let mut scanner = Scanner::new(&program);
+    scanner.scan_tokens();
+    let mut parser = Parser::new(scanner.get_tokens());
+    let statements = parser.parse();
+
+    assert_eq!(0, parser.errors.len());
+    assert_eq!(3, statements.unwrap().len());
+
+    let program = "print 1 + 2".to_string();
+    let mut scanner = Scanner::new(&program);
+    scanner.scan_tokens();
+    let mut parser = Parser::new(scanner.get_tokens());
+
+    assert!(parser.parse().is_none());
+    assert_eq!(1, parser.errors.len());
+}
+
+#[test]
+pub fn error_recovery_test() {
+    use crate::scanner::scanner::Scanner;
+
+    let parse_errors = |program: &str| -> Vec<String> {
+        let mut scanner = Scanner::new(program);
+        scanner.scan_tokens();
+        let mut parser = Parser::new(scanner.get_tokens());
+        assert!(parser.parse().is_none());
+        parser.errors.iter().map(|e| e.report_msg()).collect()
+    };
+
+    // Independent errors each get reported once
+    assert_eq!(
+        vec![
+            "2: at 'print' - Expect ';' after variable declaration.",
+            "3: at ')' - Invalid Token",
+            "4: at end - Expect ';' after value.",
+        ],
+        parse_errors("var a = 1\nprint a;\nprint (1 + );\nprint a")
+    );
+
+    // A missing ';' inside a block doesn't lose the block's closing brace
+    assert_eq!(
+        vec![
+            "1: at '}' - Expect ';' after value.",
+            "2: at '=' - Expect variable name.",
+        ],
+        parse_errors("fun f() { print 1 }\nvar = 2;\nf();")
+    );
+
+    // Junk that can't start anything is skipped a token at a time rather
+    // than getting us stuck
+    assert_eq!(
+        vec!["1: at ';' - Invalid Token", "1: at '}' - Invalid Token"],
+        parse_errors("print 1;; } print 2;")
+    );
+
+    assert_eq!(
+        vec!["1: at '(' - Expect method name."],
+        parse_errors("class A { (x) { return x; } }\nprint A;")
+    );
+}
@@ -4,7 +4,10 @@ use crate::scanner;
 
 use lox_error::lox_error::LoxError;
 use parser::parser::pstructs::Accept;
-use parser::parser::pstructs::{binary, grouping, literal, unary, Visitor};
+use parser::parser::pstructs::{
+    assign, binary, call, get, grouping, interpolation, literal, logical, set, super_expr, this,
+    unary, variable, Visitor,
+};
 use parser::parser::ParseReturn;
 // Without the "unused" exemption rustc claims that token::Token is unused
 // although it is most certainly is used and will give an unresolved error if I remove
@@ -40,42 +43,83 @@ macro_rules! parenthesize {
 }
 
 impl Visitor for AstPrinter {
+    fn assign(&mut self, expr: &assign) -> Result<ParseReturn, LoxError> {
+        parenthesize!(self, &format!("= {}", expr.name.lexeme) => expr.value)
+    }
     fn binary(&mut self, expr: &binary) -> Result<ParseReturn, LoxError> {
         parenthesize!(self, &expr.operator.lexeme => expr.left, expr.right)
     }
+    fn call(&mut self, expr: &call) -> Result<ParseReturn, LoxError> {
+        let mut result = "(call".to_string();
+        for arg in std::iter::once(&expr.callee).chain(expr.arguments.iter()) {
+            result += " ";
+            result += &self.pretty_print_value(&**arg);
+        }
+        Ok(ParseReturn::PP(result + ")"))
+    }
+    fn get(&mut self, expr: &get) -> Result<ParseReturn, LoxError> {
+        parenthesize!(self, &format!(". {}", expr.name.lexeme) => expr.object)
+    }
     fn grouping(&mut self, expr: &grouping) -> Result<ParseReturn, LoxError> {
         parenthesize!(self, "group" => expr.expression)
     }
+    fn interpolation(&mut self, expr: &interpolation) -> Result<ParseReturn, LoxError> {
+        let mut result = "(interpolate".to_string();
+        for part in &expr.parts {
+            result += " ";
+            result += &self.pretty_print_value(&**part);
+        }
+        Ok(ParseReturn::PP(result + ")"))
+    }
     fn literal(&mut self, expr: &literal) -> Result<ParseReturn, LoxError> {
         match &expr.value {
-            TokenType::Number(n) => Ok(ParseReturn::PP(format!(
-                "{}",
-                str::parse::<f64>(n).unwrap()
-            ))),
+            // Shown just as it was written so 0xff stays 0xff
+            TokenType::Number(n) => Ok(ParseReturn::PP(n.clone())),
             TokenType::String(s) => Ok(ParseReturn::PP(format!("\"{}\"", s))),
             _ => Ok(ParseReturn::PP(
                 "Non-Literal TokenType in Pretty Print".to_string(),
             )),
         }
     }
+    fn logical(&mut self, expr: &logical) -> Result<ParseReturn, LoxError> {
+        parenthesize!(self, &expr.operator.lexeme => expr.left, expr.right)
+    }
+    fn set(&mut self, expr: &set) -> Result<ParseReturn, LoxError> {
+        parenthesize!(self, &format!("= .{}", expr.name.lexeme) => expr.object, expr.value)
+    }
+    fn super_expr(&mut self, expr: &super_expr) -> Result<ParseReturn, LoxError> {
+        Ok(ParseReturn::PP(format!("(super {})", expr.method.lexeme)))
+    }
+    fn this(&mut self, _expr: &this) -> Result<ParseReturn, LoxError> {
+        Ok(ParseReturn::PP("this".to_string()))
+    }
     fn unary(&mut self, expr: &unary) -> Result<ParseReturn, LoxError> {
         parenthesize!(self, &expr.operator.lexeme => expr.right)
     }
+    fn variable(&mut self, expr: &variable) -> Result<ParseReturn, LoxError> {
+        Ok(ParseReturn::PP(expr.name.lexeme.to_string()))
+    }
 }
 
 #[test]
 pub fn pretty_print_test() {
-    let num1_lit = literal::new(TokenType::Number("123".to_string()));
-    let num2_lit = literal::new(TokenType::Number("45.67".to_string()));
-    let grouping_expr = grouping::new(Box::new(num2_lit));
+    use crate::scanner::token::Span;
+
+    // The printer doesn't care where anything came from
+    let span = Span::default();
+    let num1_lit = literal::new(TokenType::Number("123".to_string()), span);
+    let num2_lit = literal::new(TokenType::Number("45.67".to_string()), span);
+    let grouping_expr = grouping::new(Box::new(num2_lit), span);
     let unary_expr = unary::new(
-        Token::new(&TokenType::Minus, &"-".to_string(), 1),
+        Token::new(&TokenType::Minus, "-", 1),
         Box::new(num1_lit),
+        span,
     );
     let expr = binary::new(
         Box::new(unary_expr),
-        Token::new(&TokenType::Star, &"*".to_string(), 1),
+        Token::new(&TokenType::Star, "*", 1),
         Box::new(grouping_expr),
+        span,
     );
 
     assert_eq!(
@@ -83,3 +127,33 @@ pub fn pretty_print_test() {
         AstPrinter {}.pretty_print_value(&expr)
     );
 }
+
+#[test]
+pub fn operator_precedence_test() {
+    use crate::parser::parser::Parser;
+    use crate::parser::statement::sstructs;
+    use crate::scanner::scanner::Scanner;
+
+    let print = |program: &str| {
+        let mut scanner = Scanner::new(program);
+        scanner.scan_tokens();
+        let statements = Parser::new(scanner.get_tokens()).parse().unwrap();
+        let stmt = statements[0]
+            .as_any()
+            .downcast_ref::<sstructs::expression>()
+            .unwrap();
+        AstPrinter {}.pretty_print_value(&*stmt.expression)
+    };
+
+    assert_eq!("(** 2 (** 3 2))", print("2 ** 3 ** 2;"));
+    assert_eq!("(- (** 2 2))", print("-2 ** 2;"));
+    assert_eq!("(** 2 (- 1))", print("2 ** -1;"));
+    assert_eq!("(% (* 1 2) 3)", print("1 * 2 % 3;"));
+    assert_eq!(
+        "(| (^ a (& b (<< c 1))) (>> d 2))",
+        print("a ^ b & c << 1 | d >> 2;")
+    );
+    assert_eq!("(== (& a 1) 0)", print("a & 1 == 0;"));
+    assert_eq!("(~/ (~ 7) 2)", print("~7 ~/ 2;"));
+    assert_eq!("(+ 0xff (* 0b1010 1_000))", print("0xff + 0b1010 * 1_000;"));
+}
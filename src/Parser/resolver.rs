@@ -0,0 +1,374 @@
+use crate::lox_error;
+use crate::parser;
+use crate::scanner;
+
+use lox_error::lox_error::{LoxError, LoxErrorList};
+use parser::parser::pstructs::{
+    assign, binary, call, get, grouping, interpolation, literal, logical, set, super_expr, this,
+    unary, variable, Accept, Visitor,
+};
+use parser::parser::{ParseReturn, Stmt};
+use parser::statement::sstructs;
+use scanner::symbol::Symbol;
+use scanner::token::Token;
+use std::cell::Cell;
+use std::collections::HashMap;
+
+// What sort of function body we're in the middle of resolving, if any
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionType {
+    None,
+    Function,
+    Initializer,
+    Method,
+}
+
+// Whether we're inside a class declaration so we know if "this" is legal
+#[derive(Clone, Copy, PartialEq)]
+enum ClassType {
+    None,
+    Class,
+    Subclass,
+}
+
+// The resolver makes one pass over the AST between parsing and evaluation.
+// For every variable use it works out how many scopes out the declaration
+// lives and stores that right in the node so the evaluator can go straight
+// to it.  Globals aren't tracked in scopes at all - if we can't find a
+// variable locally it gets left as None and the evaluator looks in the
+// globals.  Along the way it reports the errors we can catch statically.
+pub struct Resolver {
+    // The bool is whether the variable's initializer has been resolved yet
+    scopes: Vec<HashMap<Symbol, bool>>,
+    current_function: FunctionType,
+    current_class: ClassType,
+    errors: LoxErrorList,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Resolver {
+            scopes: vec![],
+            current_function: FunctionType::None,
+            current_class: ClassType::None,
+            errors: LoxErrorList::new(),
+        }
+    }
+
+    pub fn resolve(&mut self, statements: &[Stmt]) -> LoxErrorList {
+        self.resolve_statements(statements);
+        std::mem::replace(&mut self.errors, LoxErrorList::new())
+    }
+
+    fn resolve_statements(&mut self, statements: &[Stmt]) {
+        for stmt in statements {
+            self.resolve_stmt(&**stmt);
+        }
+    }
+
+    // The resolver never fails outright - errors are collected as we go - so
+    // we can safely ignore what the visitors hand back
+    fn resolve_stmt(&mut self, stmt: &(dyn sstructs::Accept + 'static)) {
+        let _ = stmt.accept(self);
+    }
+
+    fn resolve_expr(&mut self, expr: &(dyn Accept + 'static)) {
+        let _ = expr.accept(self);
+    }
+
+    fn resolve_function(&mut self, function: &sstructs::function, ftype: FunctionType) {
+        let enclosing_function = self.current_function;
+        self.current_function = ftype;
+
+        self.begin_scope();
+        for param in &function.params {
+            self.declare(param);
+            self.define(param);
+        }
+        self.resolve_statements(&function.body);
+        self.end_scope();
+
+        self.current_function = enclosing_function;
+    }
+
+    fn resolve_local(&mut self, name: &Token, depth: &Cell<Option<usize>>) {
+        for (distance, scope) in self.scopes.iter().rev().enumerate() {
+            if scope.contains_key(&name.lexeme) {
+                depth.set(Some(distance));
+                return;
+            }
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &Token) {
+        let already_declared = match self.scopes.last_mut() {
+            None => return,
+            Some(scope) => scope.insert(name.lexeme.clone(), false).is_some(),
+        };
+
+        if already_declared {
+            self.error(name, "Already a variable with this name in this scope.");
+        }
+    }
+
+    fn define(&mut self, name: &Token) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.lexeme.clone(), true);
+        }
+    }
+
+    fn error(&mut self, token: &Token, msg: &str) {
+        self.errors.push(LoxError::new(token.clone(), msg));
+    }
+}
+
+impl sstructs::Visitor for Resolver {
+    fn block(&mut self, stmt: &sstructs::block) -> Result<ParseReturn, LoxError> {
+        self.begin_scope();
+        self.resolve_statements(&stmt.statements);
+        self.end_scope();
+        Ok(ParseReturn::Unit)
+    }
+
+    // Methods get an extra scope wrapped around them holding "this" and if
+    // there's a superclass another one outside that holding "super"
+    fn class(&mut self, stmt: &sstructs::class) -> Result<ParseReturn, LoxError> {
+        let enclosing_class = self.current_class;
+        self.current_class = ClassType::Class;
+
+        self.declare(&stmt.name);
+        self.define(&stmt.name);
+
+        if let Some(superclass) = &stmt.superclass {
+            if superclass.name.lexeme == stmt.name.lexeme {
+                self.error(&superclass.name, "A class can't inherit from itself.");
+            }
+            self.current_class = ClassType::Subclass;
+            let _ = self.variable(superclass);
+
+            self.begin_scope();
+            if let Some(scope) = self.scopes.last_mut() {
+                scope.insert(Symbol::intern("super"), true);
+            }
+        }
+
+        self.begin_scope();
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(Symbol::intern("this"), true);
+        }
+
+        for method in &stmt.methods {
+            let ftype = if method.name.lexeme == "init" {
+                FunctionType::Initializer
+            } else {
+                FunctionType::Method
+            };
+            self.resolve_function(method, ftype);
+        }
+
+        self.end_scope();
+        if stmt.superclass.is_some() {
+            self.end_scope();
+        }
+        self.current_class = enclosing_class;
+        Ok(ParseReturn::Unit)
+    }
+
+    fn expression(&mut self, stmt: &sstructs::expression) -> Result<ParseReturn, LoxError> {
+        self.resolve_expr(&*stmt.expression);
+        Ok(ParseReturn::Unit)
+    }
+
+    // The name is defined before the body is resolved so functions can recurse
+    fn function(&mut self, stmt: &sstructs::function) -> Result<ParseReturn, LoxError> {
+        self.declare(&stmt.name);
+        self.define(&stmt.name);
+        self.resolve_function(stmt, FunctionType::Function);
+        Ok(ParseReturn::Unit)
+    }
+
+    fn if_stmt(&mut self, stmt: &sstructs::if_stmt) -> Result<ParseReturn, LoxError> {
+        self.resolve_expr(&*stmt.condition);
+        self.resolve_stmt(&*stmt.then_branch);
+        if let Some(else_branch) = &stmt.else_branch {
+            self.resolve_stmt(&**else_branch);
+        }
+        Ok(ParseReturn::Unit)
+    }
+
+    fn print(&mut self, stmt: &sstructs::print) -> Result<ParseReturn, LoxError> {
+        self.resolve_expr(&*stmt.expression);
+        Ok(ParseReturn::Unit)
+    }
+
+    fn return_stmt(&mut self, stmt: &sstructs::return_stmt) -> Result<ParseReturn, LoxError> {
+        if self.current_function == FunctionType::None {
+            self.error(&stmt.keyword, "Can't return from top-level code.");
+        }
+
+        if let Some(value) = &stmt.value {
+            if self.current_function == FunctionType::Initializer {
+                self.error(&stmt.keyword, "Can't return a value from an initializer.");
+            }
+            self.resolve_expr(&**value);
+        }
+        Ok(ParseReturn::Unit)
+    }
+
+    // Declaring and defining separately lets us catch "var a = a;"
+    fn var(&mut self, stmt: &sstructs::var) -> Result<ParseReturn, LoxError> {
+        self.declare(&stmt.name);
+        if let Some(initializer) = &stmt.initializer {
+            self.resolve_expr(&**initializer);
+        }
+        self.define(&stmt.name);
+        Ok(ParseReturn::Unit)
+    }
+
+    fn while_stmt(&mut self, stmt: &sstructs::while_stmt) -> Result<ParseReturn, LoxError> {
+        self.resolve_expr(&*stmt.condition);
+        self.resolve_stmt(&*stmt.body);
+        Ok(ParseReturn::Unit)
+    }
+}
+
+impl Visitor for Resolver {
+    fn assign(&mut self, expr: &assign) -> Result<ParseReturn, LoxError> {
+        self.resolve_expr(&*expr.value);
+        self.resolve_local(&expr.name, &expr.depth);
+        Ok(ParseReturn::Unit)
+    }
+
+    fn binary(&mut self, expr: &binary) -> Result<ParseReturn, LoxError> {
+        self.resolve_expr(&*expr.left);
+        self.resolve_expr(&*expr.right);
+        Ok(ParseReturn::Unit)
+    }
+
+    fn call(&mut self, expr: &call) -> Result<ParseReturn, LoxError> {
+        self.resolve_expr(&*expr.callee);
+        for argument in &expr.arguments {
+            self.resolve_expr(&**argument);
+        }
+        Ok(ParseReturn::Unit)
+    }
+
+    fn get(&mut self, expr: &get) -> Result<ParseReturn, LoxError> {
+        self.resolve_expr(&*expr.object);
+        Ok(ParseReturn::Unit)
+    }
+
+    fn grouping(&mut self, expr: &grouping) -> Result<ParseReturn, LoxError> {
+        self.resolve_expr(&*expr.expression);
+        Ok(ParseReturn::Unit)
+    }
+
+    fn interpolation(&mut self, expr: &interpolation) -> Result<ParseReturn, LoxError> {
+        for part in &expr.parts {
+            self.resolve_expr(&**part);
+        }
+        Ok(ParseReturn::Unit)
+    }
+
+    fn literal(&mut self, _expr: &literal) -> Result<ParseReturn, LoxError> {
+        Ok(ParseReturn::Unit)
+    }
+
+    fn logical(&mut self, expr: &logical) -> Result<ParseReturn, LoxError> {
+        self.resolve_expr(&*expr.left);
+        self.resolve_expr(&*expr.right);
+        Ok(ParseReturn::Unit)
+    }
+
+    // Property names are looked up dynamically so only the object and value
+    // need resolving
+    fn set(&mut self, expr: &set) -> Result<ParseReturn, LoxError> {
+        self.resolve_expr(&*expr.value);
+        self.resolve_expr(&*expr.object);
+        Ok(ParseReturn::Unit)
+    }
+
+    fn super_expr(&mut self, expr: &super_expr) -> Result<ParseReturn, LoxError> {
+        match self.current_class {
+            ClassType::None => self.error(&expr.keyword, "Can't use 'super' outside of a class."),
+            ClassType::Class => self.error(
+                &expr.keyword,
+                "Can't use 'super' in a class with no superclass.",
+            ),
+            ClassType::Subclass => self.resolve_local(&expr.keyword, &expr.depth),
+        }
+        Ok(ParseReturn::Unit)
+    }
+
+    fn this(&mut self, expr: &this) -> Result<ParseReturn, LoxError> {
+        if self.current_class == ClassType::None {
+            self.error(&expr.keyword, "Can't use 'this' outside of a class.");
+        } else {
+            self.resolve_local(&expr.keyword, &expr.depth);
+        }
+        Ok(ParseReturn::Unit)
+    }
+
+    fn unary(&mut self, expr: &unary) -> Result<ParseReturn, LoxError> {
+        self.resolve_expr(&*expr.right);
+        Ok(ParseReturn::Unit)
+    }
+
+    fn variable(&mut self, expr: &variable) -> Result<ParseReturn, LoxError> {
+        let in_own_initializer = self
+            .scopes
+            .last()
+            .and_then(|scope| scope.get(&expr.name.lexeme))
+            == Some(&false);
+        if in_own_initializer {
+            self.error(
+                &expr.name,
+                "Can't read local variable in its own initializer.",
+            );
+        }
+
+        self.resolve_local(&expr.name, &expr.depth);
+        Ok(ParseReturn::Unit)
+    }
+}
+
+#[test]
+pub fn resolver_test() {
+    use parser::evaluate::run_program;
+
+    // Without the resolver the second call would find the block's a
+    let (output, errors) = run_program(
+        "var a = \"global\";
+        {
+            fun showA() { print a; }
+            showA();
+            var a = \"block\";
+            showA();
+            print a;
+        }",
+    );
+    assert_eq!(0, errors.len());
+    assert_eq!("global\nglobal\nblock\n", output);
+
+    let (_, errors) = run_program("var a = 1; { var a = a; }");
+    assert_eq!(1, errors.len());
+
+    let (_, errors) = run_program("fun f() { var a = 1; var a = 2; }");
+    assert_eq!(1, errors.len());
+
+    let (_, errors) = run_program("return 1;");
+    assert_eq!(1, errors.len());
+
+    // These are fine at global scope
+    let (output, errors) = run_program("var a = 1; var a = a + 1; print a;");
+    assert_eq!(0, errors.len());
+    assert_eq!("2\n", output);
+}
@@ -1,14 +1,29 @@
 pub mod sstructs {
     use crate::lox_error::lox_error::LoxError;
     use crate::parser::parser::ParseReturn;
+    use crate::scanner::token::Token;
     use crate::{build_struct, build_structs, exprType};
 
+    // if, return and while are rust keywords so those get a suffix
     build_structs! {
+        block : stmts statements;
+        class : Token name, opt_variable superclass, functions methods;
         expression : expr expression;
+        function : Token name, tokens params, shared_stmts body;
+        if_stmt : expr condition, stmt then_branch, opt_stmt else_branch;
         print : expr expression;
+        return_stmt : Token keyword, opt_expr value;
+        var : Token name, opt_expr initializer;
+        while_stmt : expr condition, stmt body;
     }
 
     pub trait Accept {
         fn accept(&self, visitor: &mut dyn Visitor) -> Result<ParseReturn, LoxError>;
+        #[allow(unused)]
+        fn span(&self) -> crate::scanner::token::Span;
+        #[allow(unused)]
+        fn as_any(&self) -> &dyn std::any::Any;
+        #[allow(unused)]
+        fn into_any(self: Box<Self>) -> Box<dyn std::any::Any>;
     }
 }
@@ -8,19 +8,23 @@ macro_rules! build_struct {
             $(
                 pub $name: exprType!($type),
             )*
+            // Every node knows what part of the source it came from
+            pub span: $crate::scanner::token::Span,
         }
 
         #[allow(unused)]
         impl $struct_name {
             pub fn new(
                 $(
-                    $name: exprType!($type)
-                ),*
+                    $name: exprType!($type),
+                )*
+                span: $crate::scanner::token::Span,
             ) -> Self {
                 $struct_name {
                     $(
-                        $name
-                    ),*
+                        $name,
+                    )*
+                    span,
                 }
             }
         }
@@ -29,13 +33,39 @@ macro_rules! build_struct {
             fn accept(&self, visitor: &mut dyn Visitor) -> Result<ParseReturn, LoxError> {
                 visitor.$struct_name(self)
             }
+
+            fn span(&self) -> $crate::scanner::token::Span {
+                self.span
+            }
+
+            fn as_any(&self) -> &dyn std::any::Any {
+                self
+            }
+
+            fn into_any(self: Box<Self>) -> Box<dyn std::any::Any> {
+                self
+            }
         }
     )
 }
 
 #[macro_export]
 macro_rules! exprType {
-    (expr) => (Box<dyn Accept>);
+    (expr) => (Box<dyn $crate::parser::parser::pstructs::Accept>);
+    (stmt) => (Box<dyn $crate::parser::statement::sstructs::Accept>);
+    (opt_stmt) => (Option<Box<dyn $crate::parser::statement::sstructs::Accept>>);
+    (stmts) => (Vec<Box<dyn $crate::parser::statement::sstructs::Accept>>);
+    (exprs) => (Vec<Box<dyn $crate::parser::parser::pstructs::Accept>>);
+    (opt_expr) => (Option<Box<dyn $crate::parser::parser::pstructs::Accept>>);
+    // Function bodies are shared with the function values created from them
+    // so they can outlive the AST they were parsed into
+    (shared_stmts) => (std::rc::Rc<Vec<Box<dyn $crate::parser::statement::sstructs::Accept>>>);
+    (tokens) => (Vec<$crate::scanner::token::Token>);
+    (opt_variable) => (Option<$crate::parser::parser::pstructs::variable>);
+    (functions) => (Vec<$crate::parser::statement::sstructs::function>);
+    // Filled in by the resolver after parsing - the number of scopes between
+    // a variable's use and its declaration or None if it's a global
+    (scope_depth) => (std::cell::Cell<Option<usize>>);
     ($type: ident) => ($type);
 }
 
@@ -1,11 +1,14 @@
 use crate::scanner;
-use scanner::token::Token;
+use scanner::token::{SourceId, Span, Token};
 use scanner::token_type::TokenType;
 
 #[derive(Clone)]
 pub struct LoxError {
-    token_option: Option<Token>,
+    // Boxed so passing errors around in Results stays cheap
+    token_option: Option<Box<Token>>,
     line_option: Option<usize>,
+    // The part of the source to underline when we've got the source to show
+    span_option: Option<Span>,
     text: String,
 }
 
@@ -14,7 +17,12 @@ impl LoxError {
     pub fn new(token: Token, text: &str) -> LoxError {
         LoxError {
             line_option: Some(token.line),
-            token_option: Some(token),
+            span_option: if token.is_from_source() {
+                Some(token.span())
+            } else {
+                None
+            },
+            token_option: Some(Box::new(token)),
             // I think I should probably ma
             text: text.to_string(),
         }
@@ -23,6 +31,7 @@ impl LoxError {
     pub fn new_text_only(line_number: Option<usize>, text: &str) -> LoxError {
         LoxError {
             line_option: line_number,
+            span_option: None,
             token_option: None,
             text: text.to_string(),
         }
@@ -42,20 +51,92 @@ impl LoxError {
         }
     }
 
+    // Lets an error point at more than just its token - the whole of a
+    // bad expression rather than only its operator for instance
+    pub fn with_span(mut self, span: Span) -> LoxError {
+        self.span_option = Some(span);
+        self
+    }
+
     pub fn report(&self) {
         println!("{}", self.report_msg());
     }
+
+    pub fn report_with_source(&self, source: &str) {
+        println!("{}", self.render(source));
+    }
+
+    // The usual message followed, if we know where the error is, by the line
+    // it's on with the offending part underlined:
+    //
+    //     1: at '-' - Expected number but found string
+    //       |
+    //     1 | print -"oops";
+    //       |       ^^^^^^^
+    //
+    // A span from some other text, like a function defined on an earlier
+    // REPL line, just gets the message.
+    pub fn render(&self, source: &str) -> String {
+        let msg = self.report_msg();
+        match self.span_option {
+            Some(span) if span.source == SourceId::of(source) => {
+                match Self::underline(span, source) {
+                    Some(underlined) => format!("{}\n{}", msg, underlined),
+                    None => msg,
+                }
+            }
+            _ => msg,
+        }
+    }
+
+    // Everything is sliced with get() so a span that doesn't fit the source
+    // gives None rather than a panic
+    fn underline(span: Span, source: &str) -> Option<String> {
+        let before = source.get(..span.start)?;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source
+            .get(span.start..)?
+            .find('\n')
+            .map_or(source.len(), |i| i + span.start);
+        let line_text = source.get(line_start..line_end)?.trim_end_matches('\r');
+        let line_number = before.get(..line_start)?.matches('\n').count() + 1;
+
+        // Only underline up to the end of the first line.  Tabs stay tabs so
+        // the carets line up however wide the terminal makes them.
+        let padding: String = before
+            .get(line_start..)?
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let underline_end = span.end.min(line_start + line_text.len()).max(span.start);
+        let carets = source
+            .get(span.start..underline_end)?
+            .chars()
+            .count()
+            .max(1);
+
+        let gutter = " ".repeat(line_number.to_string().len());
+        Some(format!(
+            "{} |\n{} | {}\n{} | {}{}",
+            gutter,
+            line_number,
+            line_text,
+            gutter,
+            padding,
+            "^".repeat(carets)
+        ))
+    }
 }
 
 #[test]
 pub fn error_test() {
-    let token = Token::new(&TokenType::And, &"&".to_string(), 10);
+    let token = Token::new(&TokenType::And, "&", 10);
     let err = LoxError::new(token, "Test with normal token");
     let text = err.report_msg();
 
     assert_eq!("10: at '&' - Test with normal token", text);
 
-    let token = Token::new(&TokenType::Eof, &"".to_string(), 20);
+    let token = Token::new(&TokenType::Eof, "", 20);
     let err = LoxError::new(token, "Test with EOF token");
     let text = err.report_msg();
 
@@ -72,6 +153,39 @@ pub fn error_test() {
     assert_eq!("30: Test with only text and line number", text);
 }
 
+#[test]
+pub fn render_test() {
+    use crate::parser::evaluate::run_program;
+
+    let program = "var a = 1;\nprint -\"oops\" + a;";
+    let (_, errors) = run_program(program);
+    let rendered: Vec<String> = errors.iter().map(|e| e.render(program)).collect();
+    assert_eq!(1, rendered.len());
+    assert!(rendered[0].ends_with("\n  |\n2 | print -\"oops\" + a;\n  |       ^^^^^^^"));
+
+    // Binary operators underline both operands
+    let program = "print 1 < \"two\";";
+    let (_, errors) = run_program(program);
+    let rendered = errors.iter().next().unwrap().render(program);
+    assert!(rendered.ends_with("1 | print 1 < \"two\";\n  |       ^^^^^^^^^"));
+
+    // Without a span we just get the message
+    let err = LoxError::new_text_only(Some(3), "No span here");
+    assert_eq!("3: No span here", err.render(program));
+
+    // Nor when the span came from some other text, as it does when the REPL
+    // calls a function from an earlier line
+    let (_, errors) = run_program("fun  f() { return -\"x\"; } f();");
+    let error = errors.iter().next().unwrap();
+    let later = "print \"\u{e9}\u{e9}\u{e9}\u{e9}\u{e9}\u{e9}\u{e9}\u{e9}\u{e9}\"; f();";
+    assert_eq!(error.report_msg(), error.render(later));
+
+    // And a span that doesn't fit its source can't make us panic
+    let err = LoxError::new_text_only(Some(1), "Bad span")
+        .with_span(Span::new(8, 10).in_source(SourceId::of(later)));
+    assert_eq!("1: Bad span", err.render(later));
+}
+
 #[derive(Clone)]
 pub struct LoxErrorList {
     errors: Vec<LoxError>,
@@ -99,9 +213,19 @@ impl LoxErrorList {
         self.errors.len()
     }
 
+    pub fn iter(&self) -> std::slice::Iter<'_, LoxError> {
+        self.errors.iter()
+    }
+
     pub fn report(&self) -> () {
         for error in self.errors.iter() {
             error.report();
         }
     }
+
+    pub fn report_with_source(&self, source: &str) {
+        for error in self.errors.iter() {
+            error.report_with_source(source);
+        }
+    }
 }
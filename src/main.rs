@@ -2,7 +2,7 @@ mod lox_error;
 mod parser;
 mod scanner;
 mod setup;
-extern crate ascii;
+mod vm;
 extern crate colored;
 extern crate lazy_static;
 
@@ -1,3 +1,4 @@
 pub mod scanner;
+pub mod symbol;
 pub mod token;
 pub mod token_type;
@@ -1,35 +1,63 @@
-use crate::ascii::AsciiStr;
 use crate::lox_error;
 use crate::scanner;
 use lox_error::{lox_error::LoxError, lox_error::LoxErrorList};
-use scanner::{token::Token, token_type::TokenType};
+use scanner::{symbol::Symbol, token::SourceId, token::Span, token::Token, token_type::TokenType};
+// start and current are byte offsets into source so they always sit on a
+// char boundary and can be used to slice out lexemes.  Columns on the other
+// hand are counted in chars since that's what a person looking at the line
+// would count.
 pub struct Scanner<'a> {
     start: usize,
     current: usize,
     line: usize,
+    column: usize,
+
+    // Where the token currently being scanned began
+    start_line: usize,
+    start_column: usize,
+
+    // One entry per "${" we're currently inside of holding the number of
+    // braces opened since then and the line its string started on.  The "}"
+    // that closes the interpolation is the one seen when the count is 0.
+    interpolations: Vec<(usize, usize)>,
+
+    // Whether comments turn into Comment tokens or just disappear.  Tools
+    // like formatters want them but the parser doesn't.
+    keep_comments: bool,
 
     tokens: Vec<Token>,
     errors: LoxErrorList,
-    source: &'a AsciiStr,
+    source: &'a str,
+    // Every span we make is tagged with this so errors are only ever
+    // drawn against the text they came from
+    source_id: SourceId,
 }
 
 #[allow(unused)]
 impl<'a> Scanner<'a> {
-    pub fn new(program: &'a String) -> Result<Scanner<'a>, LoxError> {
-        let test = AsciiStr::from_ascii(program);
-        let ascii_str = match test {
-            Err(_) => return Err(LoxError::new_text_only(None, "Program should be in ascii")),
-            Ok(a) => a,
-        };
-        let scanner = Scanner {
+    pub fn new(program: &'a str) -> Scanner<'a> {
+        Scanner {
             start: 0,
             current: 0,
             line: 1,
-            source: ascii_str,
+            column: 1,
+            start_line: 1,
+            start_column: 1,
+            interpolations: vec![],
+            keep_comments: false,
+            source: program,
+            source_id: SourceId::of(program),
             tokens: vec![],
             errors: LoxErrorList::new(),
-        };
-        Ok(scanner)
+        }
+    }
+
+    // A scanner that hands back comments as Comment tokens along with
+    // everything else
+    pub fn with_comments(program: &'a str) -> Scanner<'a> {
+        let mut scanner = Scanner::new(program);
+        scanner.keep_comments = true;
+        scanner
     }
 
     pub fn add_token(&mut self, token: Token) {
@@ -37,7 +65,23 @@ impl<'a> Scanner<'a> {
     }
 
     pub fn add_token_type(&mut self, tt: &TokenType) {
-        self.add_token(Token::new(tt, &tt.to_stringslice().to_string(), self.line))
+        let lexeme = tt.to_stringslice().to_string();
+        self.add_token(self.make_token(tt, &lexeme))
+    }
+
+    // Tokens are positioned where they start, not where we are now
+    fn make_token(&self, tt: &TokenType, lexeme: &String) -> Token {
+        Token::new_at(
+            tt,
+            lexeme,
+            self.start_line,
+            self.start_column,
+            self.span(self.start, self.current),
+        )
+    }
+
+    fn span(&self, start: usize, end: usize) -> Span {
+        Span::new(start, end).in_source(self.source_id)
     }
 
     pub fn get_tokens(self) -> Vec<Token> {
@@ -52,14 +96,29 @@ impl<'a> Scanner<'a> {
         self.current >= self.source.len()
     }
 
+    // Lexical errors don't stop us - we note them and carry on so everything
+    // wrong with the source gets reported in one go
     pub fn scan_tokens(&mut self) {
-        let mut errors = LoxErrorList::new();
-
         while !self.is_at_end() {
             self.start = self.current;
+            self.start_line = self.line;
+            self.start_column = self.column;
             self.scan_token();
         }
-        self.add_token(Token::new(&TokenType::Eof, &"".to_string(), self.line));
+
+        if let Some((_, line)) = self.interpolations.pop() {
+            self.errors.push(LoxError::new_text_only(
+                Some(line),
+                "Unterminated string interpolation.",
+            ));
+        }
+        self.add_token(Token::new_at(
+            &TokenType::Eof,
+            "",
+            self.line,
+            self.column,
+            self.span(self.current, self.current),
+        ));
     }
 
     pub fn scan_token(&mut self) {
@@ -68,16 +127,35 @@ impl<'a> Scanner<'a> {
             // unambiguous single characters
             '(' => self.add_token_type(&TokenType::LeftParen),
             ')' => self.add_token_type(&TokenType::RightParen),
-            '{' => self.add_token_type(&TokenType::LeftBrace),
-            '}' => self.add_token_type(&TokenType::RightBrace),
+            '{' => {
+                if let Some((braces, _)) = self.interpolations.last_mut() {
+                    *braces += 1;
+                }
+                self.add_token_type(&TokenType::LeftBrace);
+            }
+            '}' => match self.interpolations.last_mut() {
+                Some((0, _)) => {
+                    // Back to the string we were in before the "${"
+                    self.interpolations.pop();
+                    self.scan_string();
+                }
+                Some((braces, _)) => {
+                    *braces -= 1;
+                    self.add_token_type(&TokenType::RightBrace);
+                }
+                None => self.add_token_type(&TokenType::RightBrace),
+            },
             ',' => self.add_token_type(&TokenType::Comma),
             '.' => self.add_token_type(&TokenType::Dot),
             '-' => self.add_token_type(&TokenType::Minus),
             '+' => self.add_token_type(&TokenType::Plus),
             ';' => self.add_token_type(&TokenType::Semicolon),
-            '*' => self.add_token_type(&TokenType::Star),
+            '%' => self.add_token_type(&TokenType::Percent),
+            '&' => self.add_token_type(&TokenType::Ampersand),
+            '|' => self.add_token_type(&TokenType::Pipe),
+            '^' => self.add_token_type(&TokenType::Caret),
 
-            // Two letter combos ending with '='
+            // Two letter combos - mostly ending with '='
             '!' => {
                 let tt = if self.match_ch('=') {
                     &TokenType::BangEqual
@@ -97,6 +175,8 @@ impl<'a> Scanner<'a> {
             '<' => {
                 let tt = if self.match_ch('=') {
                     &TokenType::LessEqual
+                } else if self.match_ch('<') {
+                    &TokenType::LessLess
                 } else {
                     &TokenType::Less
                 };
@@ -105,24 +185,46 @@ impl<'a> Scanner<'a> {
             '>' => {
                 let tt = if self.match_ch('=') {
                     &TokenType::GreaterEqual
+                } else if self.match_ch('>') {
+                    &TokenType::GreaterGreater
                 } else {
                     &TokenType::Greater
                 };
                 self.add_token_type(tt);
             }
+            // Two letter combos that aren't just the first letter and '='
+            '*' => {
+                let tt = if self.match_ch('*') {
+                    &TokenType::StarStar
+                } else {
+                    &TokenType::Star
+                };
+                self.add_token_type(tt);
+            }
+            // Integer division.  "//" would have been nicer but it's taken.
+            '~' => {
+                let tt = if self.match_ch('/') {
+                    &TokenType::TildeSlash
+                } else {
+                    &TokenType::Tilde
+                };
+                self.add_token_type(tt);
+            }
             '/' => {
                 if self.match_ch('/') {
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                    self.add_comment(2, 0);
+                } else if self.match_ch('*') {
+                    self.scan_block_comment();
                 } else {
                     self.add_token_type(&TokenType::Slash);
                 }
             }
 
-            // White Space
-            ' ' | '\r' | '\t' => {}
-            '\n' => self.line += 1,
+            // White Space - advance() takes care of counting lines
+            ' ' | '\r' | '\t' | '\n' => {}
 
             // Strings
             '"' => self.scan_string(),
@@ -132,111 +234,474 @@ impl<'a> Scanner<'a> {
 
             // Everything else
             _ => {
-                if Self::is_id_char(c) {
+                if Self::is_id_start(c) {
                     self.scan_identifier();
                 } else {
-                    self.errors.push(LoxError::new_text_only(
-                        Some(self.line),
-                        "Unexpected character.",
-                    ));
+                    self.unexpected_character(c);
                 }
             }
         };
     }
 
+    fn unexpected_character(&mut self, c: char) {
+        let token = self.make_token(&TokenType::Error, &c.to_string());
+        self.errors
+            .push(LoxError::new(token, "Unexpected character."));
+    }
+
+    // Block comments nest so commenting out code that already has a block
+    // comment in it works.  advance() keeps the line count right for us.
+    fn scan_block_comment(&mut self) {
+        let mut depth = 1;
+        while depth > 0 {
+            if self.is_at_end() {
+                // Like strings we point at where it started
+                let error =
+                    LoxError::new_text_only(Some(self.start_line), "Unterminated block comment.")
+                        .with_span(self.span(self.start, self.start + 2));
+                self.errors.push(error);
+                return;
+            }
+
+            if self.peek() == '/' && self.peek_next() == '*' {
+                self.advance();
+                self.advance();
+                depth += 1;
+            } else if self.peek() == '*' && self.peek_next() == '/' {
+                self.advance();
+                self.advance();
+                depth -= 1;
+            } else {
+                self.advance();
+            }
+        }
+        self.add_comment(2, 2);
+    }
+
+    // opening and closing are the lengths of the comment's delimiters
+    fn add_comment(&mut self, opening: usize, closing: usize) {
+        if self.keep_comments {
+            let lexeme = self.source[self.start..self.current].to_string();
+            let text = lexeme[opening..lexeme.len() - closing].to_string();
+            let token = self.make_token(&TokenType::Comment(text), &lexeme);
+            self.add_token(token);
+        }
+    }
+
     fn scan_identifier(&mut self) {
         while Self::is_id_char(self.peek()) {
             self.advance();
         }
         let text = &self.source[self.start..self.current].to_string();
         let tt = match TokenType::to_keyword(&text[..]) {
-            None => TokenType::Identifier(text.to_string()),
+            None => TokenType::Identifier(Symbol::intern(text)),
             Some(v) => v.clone(),
         };
-        let token = Token::new(&tt, &text.to_string(), self.line);
+        let token = self.make_token(&tt, &text.to_string());
         self.add_token(token);
     }
 
+    // Any letter in any script will do - not just ascii ones
+    fn is_id_start(c: char) -> bool {
+        c.is_alphabetic() || c == '_'
+    }
+
     fn is_id_char(c: char) -> bool {
         c.is_alphanumeric() || c == '_'
     }
 
+    // The token's value has escapes replaced by what they stand for while the
+    // lexeme keeps the string exactly as it was written.  We also end up here
+    // to finish off a string after each "${...}" in it.
     fn scan_string(&mut self) {
+        let mut value = String::new();
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1
+            let c = self.advance();
+            if c == '\\' {
+                if let Some(escaped) = self.scan_escape() {
+                    value.push(escaped);
+                }
+            } else if c == '$' && self.match_ch('{') {
+                let lexeme = self.source[self.start..self.current].to_string();
+                let token = self.make_token(&TokenType::Interpolation(value), &lexeme);
+                self.add_token(token);
+                self.interpolations.push((0, self.start_line));
+                return;
+            } else {
+                value.push(c);
             }
-            self.advance();
         }
 
+        // Pointing at the end of the file wouldn't be much help so we
+        // report the line the string started on
         if self.is_at_end() {
-            self.errors
-                .push(LoxError::new_text_only(None, "Unterminated string."));
+            // The opening quote is what gets underlined
+            let error = LoxError::new_text_only(Some(self.start_line), "Unterminated string.")
+                .with_span(self.span(self.start, self.start + 1));
+            self.errors.push(error);
             return;
         }
 
         // Terminating double quote
         self.advance();
 
-        let text = &self.source[self.start + 1..self.current - 1].to_string();
-        let token = Token::new(
-            &TokenType::String(text.clone()),
-            &format!("{}{}{}", '"', text, '"'),
-            self.line,
-        );
+        let lexeme = self.source[self.start..self.current].to_string();
+        let token = self.make_token(&TokenType::String(Symbol::intern(&value)), &lexeme);
         self.add_token(token)
     }
 
-    fn scan_number(&mut self, init: char) {
-        while self.peek().is_digit(10) {
+    // Called just after the backslash.  Bad escapes are reported and dropped
+    // from the string but we keep scanning it so we can find any others.
+    fn scan_escape(&mut self) -> Option<char> {
+        // The backslash is one byte and one column back
+        let escape_start = self.current - 1;
+        let line = self.line;
+        let column = self.column - 1;
+
+        let escaped = match self.peek() {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            '\\' => Some('\\'),
+            '"' => Some('"'),
+            '$' => Some('$'),
+            'u' => {
+                self.advance();
+                return self.scan_unicode_escape(escape_start, line, column);
+            }
+            _ => None,
+        };
+
+        // Leave an escaped newline or a closing quote for scan_string to see
+        if escaped.is_some() || (self.peek() != '\n' && !self.is_at_end()) {
             self.advance();
         }
+        if escaped.is_none() {
+            self.escape_error(escape_start, line, column, "Invalid escape sequence.");
+        }
+        escaped
+    }
+
+    // \u{XXXX} with one to six hex digits naming a unicode scalar value
+    fn scan_unicode_escape(
+        &mut self,
+        escape_start: usize,
+        line: usize,
+        column: usize,
+    ) -> Option<char> {
+        let bad_escape = "Invalid unicode escape - expected \\u{XXXX}.";
+        if !self.match_ch('{') {
+            self.escape_error(escape_start, line, column, bad_escape);
+            return None;
+        }
 
-        if self.peek() == '.' && self.peek_next().is_digit(10) {
-            //consume the decimal point
+        let digits_start = self.current;
+        while self.peek().is_ascii_hexdigit() {
             self.advance();
+        }
+        let digits = &self.source[digits_start..self.current];
+        if !self.match_ch('}') || digits.is_empty() || digits.len() > 6 {
+            self.escape_error(escape_start, line, column, bad_escape);
+            return None;
+        }
+
+        let escaped = u32::from_str_radix(digits, 16)
+            .ok()
+            .and_then(std::char::from_u32);
+        if escaped.is_none() {
+            self.escape_error(
+                escape_start,
+                line,
+                column,
+                "Unicode escape is not a valid character.",
+            );
+        }
+        escaped
+    }
+
+    fn escape_error(&mut self, escape_start: usize, line: usize, column: usize, msg: &str) {
+        let lexeme = self.source[escape_start..self.current].to_string();
+        let span = self.span(escape_start, self.current);
+        let token = Token::new_at(&TokenType::Error, &lexeme, line, column, span);
+        self.errors.push(LoxError::new(token, msg));
+    }
 
-            while self.peek().is_digit(10) {
+    // We're greedy here and take every letter, digit and underscore that
+    // follows so something like 0b102 or 12abc comes out as one bad number
+    // rather than a number and an identifier that confuse the parser.  Whether
+    // it's actually a valid number is left to TokenType::parse_number.
+    fn scan_number(&mut self, init: char) {
+        let radix_prefix = init == '0' && matches!(self.peek(), 'x' | 'X' | 'b' | 'B');
+        let mut seen_dot = false;
+        loop {
+            let c = self.peek();
+            if Self::is_id_char(c) {
+                let exponent = !radix_prefix && (c == 'e' || c == 'E');
                 self.advance();
+                if exponent && matches!(self.peek(), '+' | '-') && self.peek_next().is_ascii_digit()
+                {
+                    self.advance();
+                }
+            } else if c == '.'
+                && !radix_prefix
+                && !seen_dot
+                && self.peek_next().is_ascii_digit()
+                && !self.source[self.start..self.current].contains(['e', 'E'])
+            {
+                seen_dot = true;
+                self.advance();
+            } else {
+                break;
             }
         }
+
         let text = self.source[self.start..self.current].to_string();
-        let token = Token::new(&TokenType::Number(text.clone()), &text, self.line);
+        let token = self.make_token(&TokenType::Number(text.clone()), &text);
+        // A bad number still goes to the parser so it doesn't go on to
+        // complain about an expression being missing as well
+        if let Err(msg) = TokenType::parse_number(&text) {
+            self.errors.push(LoxError::new(token.clone(), msg));
+        }
         self.add_token(token);
     }
 
+    // All movement through the source goes through here so this is the one
+    // place that has to keep track of lines and columns
     fn advance(&mut self) -> char {
-        let old_index = self.current;
-        self.current += 1;
-        self.source[old_index].as_char()
+        let ch = self.source[self.current..].chars().next().unwrap();
+        self.current += ch.len_utf8();
+        if ch == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        ch
     }
 
     fn match_ch(&mut self, expected: char) -> bool {
-        if self.is_at_end() {
-            return false;
-        }
-        let ch = self.source[self.current].as_char();
-        if (ch != expected) {
+        if self.is_at_end() || self.peek() != expected {
             false
         } else {
-            self.current += 1;
+            self.advance();
             true
         }
     }
 
-    fn peek(&mut self) -> char {
-        if self.is_at_end() {
-            '\0'
-        } else {
-            self.source[self.current].as_char()
-        }
+    fn peek(&self) -> char {
+        self.source[self.current..].chars().next().unwrap_or('\0')
     }
 
     fn peek_next(&self) -> char {
-        if self.current + 1 >= self.source.len() {
-            '\0'
-        } else {
-            self.source[self.current + 1].as_char()
-        }
+        self.source[self.current..].chars().nth(1).unwrap_or('\0')
     }
 }
+
+#[test]
+pub fn unicode_test() {
+    let program = "// Grüße, 世界\nvar ñame = \"héllo 世界\"; print ñame;".to_string();
+    let mut scanner = Scanner::new(&program);
+    scanner.scan_tokens();
+    assert_eq!(0, scanner.get_errors().len());
+
+    let tokens = scanner.get_tokens();
+    assert_eq!(9, tokens.len());
+    assert!(tokens[1].ttype == TokenType::Identifier("ñame".into()));
+    assert!(tokens[3].ttype == TokenType::String("héllo 世界".into()));
+
+    // Columns count characters, not bytes
+    assert_eq!((2, 12), (tokens[3].line, tokens[3].column));
+    assert_eq!((2, 24), (tokens[5].line, tokens[5].column));
+}
+
+#[test]
+pub fn interpolation_test() {
+    let program = "\"a${x}b${ \"in${y}\" }c\" \"\\${z}\"".to_string();
+    let mut scanner = Scanner::new(&program);
+    scanner.scan_tokens();
+    assert_eq!(0, scanner.get_errors().len());
+
+    let types: Vec<String> = scanner
+        .get_tokens()
+        .iter()
+        .map(|t| format!("{}", t.ttype))
+        .collect();
+    assert_eq!(
+        vec![
+            "\"a${\"",
+            "id[\"x\"]",
+            "\"b${\"",
+            "\"in${\"",
+            "id[\"y\"]",
+            "\"\"",
+            "\"c\"",
+            "\"${z}\"",
+            "eof"
+        ],
+        types
+    );
+
+    let program = "\"open ${ 1 + \n".to_string();
+    let mut scanner = Scanner::new(&program);
+    scanner.scan_tokens();
+    assert_eq!(1, scanner.get_errors().len());
+}
+
+#[test]
+pub fn string_escape_test() {
+    let program = "\"tab\\there\\n\\\"quoted\\\" \\\\ \\u{48}\\u{1F600}\"".to_string();
+    let mut scanner = Scanner::new(&program);
+    scanner.scan_tokens();
+    assert_eq!(0, scanner.get_errors().len());
+    let tokens = scanner.get_tokens();
+    assert!(tokens[0].ttype == TokenType::String("tab\there\n\"quoted\" \\ H\u{1F600}".into()));
+    assert_eq!(program, tokens[0].lexeme);
+
+    // Every bad escape gets reported where it is
+    let program = "\n  \"a\\qb\\u{110000}\\u48\"".to_string();
+    let mut scanner = Scanner::new(&program);
+    scanner.scan_tokens();
+    let errors = scanner.get_errors();
+    assert_eq!(3, errors.len());
+    assert_eq!(
+        "2: at '\\q' - Invalid escape sequence.",
+        errors.iter().next().unwrap().report_msg()
+    );
+
+    // Unterminated strings are reported on the line they start
+    let program = "print 1;\n\"never\nending".to_string();
+    let mut scanner = Scanner::new(&program);
+    scanner.scan_tokens();
+    let errors = scanner.get_errors();
+    assert_eq!(1, errors.len());
+    assert_eq!(
+        "2: Unterminated string.",
+        errors.iter().next().unwrap().report_msg()
+    );
+}
+
+#[test]
+pub fn lexical_error_test() {
+    use crate::parser::evaluate::run_program;
+
+    let program = "var a = 1 @ 2;\nprint a; #\n\"never closed";
+    let mut scanner = Scanner::new(program);
+    scanner.scan_tokens();
+    let errors = scanner.get_errors();
+    let msgs: Vec<String> = errors.iter().map(|e| e.report_msg()).collect();
+    assert_eq!(
+        vec![
+            "1: at '@' - Unexpected character.",
+            "2: at '#' - Unexpected character.",
+            "3: Unterminated string.",
+        ],
+        msgs
+    );
+    assert!(errors
+        .iter()
+        .next()
+        .unwrap()
+        .render(program)
+        .ends_with("1 | var a = 1 @ 2;\n  |           ^"));
+
+    // Nothing gets run once the scanner has complained
+    let (output, errors) = run_program("print 1; print 2 $;");
+    assert_eq!("", output);
+    assert_eq!(1, errors.len());
+}
+
+#[test]
+pub fn block_comment_test() {
+    use crate::parser::evaluate::run_program;
+
+    let program = "/* one\n /* two */\n still one */ print 1; /**/ print 2;";
+    let mut scanner = Scanner::new(program);
+    scanner.scan_tokens();
+    assert_eq!(0, scanner.get_errors().len());
+    let tokens = scanner.get_tokens();
+    assert_eq!(7, tokens.len());
+    assert_eq!((3, 15), (tokens[0].line, tokens[0].column));
+
+    // Kept comments come back in order with everything else
+    let program = "// doc\nvar a = 1; /* block\n*/ print a;";
+    let mut scanner = Scanner::with_comments(program);
+    scanner.scan_tokens();
+    let tokens = scanner.get_tokens();
+    assert_eq!(11, tokens.len());
+    assert!(tokens[0].ttype == TokenType::Comment(" doc".to_string()));
+    assert!(tokens[6].ttype == TokenType::Comment(" block\n".to_string()));
+    assert_eq!("/* block\n*/", tokens[6].lexeme);
+    assert_eq!((3, 4), (tokens[7].line, tokens[7].column));
+
+    // ...and the parser doesn't trip over them
+    let mut parser = crate::parser::parser::Parser::new(tokens);
+    assert_eq!(2, parser.parse().unwrap().len());
+
+    let (output, errors) = run_program("print 1; /* oops /* */\nprint 2;");
+    assert_eq!("", output);
+    assert_eq!(1, errors.len());
+    assert_eq!(
+        "1: Unterminated block comment.",
+        errors.iter().next().unwrap().report_msg()
+    );
+}
+
+#[test]
+pub fn number_test() {
+    use crate::parser::evaluate::run_program;
+
+    let (output, errors) = run_program(
+        "print 0xff; print 0B1010; print 1_000_000; print 2.5e3; print 1E-2; print 0x7fff_ffff;",
+    );
+    assert_eq!(0, errors.len());
+    assert_eq!("255\n10\n1000000\n2500\n0.01\n2147483647\n", output);
+
+    // The literal keeps what was actually written
+    let mut scanner = Scanner::new("1_000.5e+2");
+    scanner.scan_tokens();
+    assert!(scanner.get_tokens()[0].ttype == TokenType::Number("1_000.5e+2".to_string()));
+
+    // Methods on numbers aren't a thing but the dot still isn't ours
+    let mut scanner = Scanner::new("1.foo");
+    scanner.scan_tokens();
+    assert_eq!(4, scanner.get_tokens().len());
+
+    let mut scanner =
+        Scanner::new("0x; 0b102; 1__0; 1_; 12abc; 1e; 1e+; 0xffff_ffff_ffff_ffff_f; 1e999;");
+    scanner.scan_tokens();
+    let msgs: Vec<String> = scanner
+        .get_errors()
+        .iter()
+        .map(|e| e.report_msg())
+        .collect();
+    assert_eq!(
+        vec![
+            "1: at '0x' - Expected digits after number prefix.",
+            "1: at '0b102' - Invalid digit in binary number.",
+            "1: at '1__0' - Misplaced '_' in number.",
+            "1: at '1_' - Misplaced '_' in number.",
+            "1: at '12abc' - Invalid number.",
+            "1: at '1e' - Invalid exponent in number.",
+            "1: at '1e' - Invalid exponent in number.",
+            "1: at '0xffff_ffff_ffff_ffff_f' - Number is too large.",
+            "1: at '1e999' - Number is too large.",
+        ],
+        msgs
+    );
+
+    // Past the integers decimals become floats but hex and binary wrap
+    // round to the negatives
+    let (output, errors) = run_program(
+        "print 9223372036854775807; print 9223372036854775808;
+        print 0xffff_ffff_ffff_ffff; print 0x8000_0000_0000_0000;",
+    );
+    assert_eq!(0, errors.len());
+    assert_eq!(
+        "9223372036854775807\n9223372036854776000\n-1\n-9223372036854775808\n",
+        output
+    );
+
+    // One bad number is one error
+    let (_, errors) = run_program("print 1e999;");
+    let msgs: Vec<String> = errors.iter().map(|e| e.report_msg()).collect();
+    assert_eq!(vec!["1: at '1e999' - Number is too large."], msgs);
+}
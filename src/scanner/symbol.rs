@@ -0,0 +1,140 @@
+use lazy_static::lazy_static;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+// An interned string.  Every Symbol with the same contents shares the one
+// allocation so comparing two is a pointer comparison and hashing one only
+// hashes the pointer - the contents get hashed once when they're interned.
+// Identifiers, string literals and Lox string values are all Symbols.
+#[derive(Clone)]
+pub struct Symbol(Arc<str>);
+
+// Start clearing out strings nobody uses any more once the table has this
+// many in it
+const MIN_NEXT_PURGE: usize = 1024;
+
+struct Table {
+    strings: HashSet<Arc<str>>,
+    next_purge: usize,
+}
+
+// Arc and a Mutex rather than anything cheaper so tokens can still live in
+// the lazy_static tables in token_type.rs
+lazy_static! {
+    static ref TABLE: Mutex<Table> = Mutex::new(Table {
+        strings: HashSet::new(),
+        next_purge: MIN_NEXT_PURGE,
+    });
+}
+
+impl Symbol {
+    pub fn intern(s: &str) -> Symbol {
+        let mut table = TABLE.lock().unwrap();
+        if let Some(existing) = table.strings.get(s) {
+            return Symbol(existing.clone());
+        }
+
+        // Strings only the table still holds can go.  Doing it when the
+        // table has doubled keeps the cost per string constant.
+        if table.strings.len() >= table.next_purge {
+            table.strings.retain(|s| Arc::strong_count(s) > 1);
+            table.next_purge = MIN_NEXT_PURGE.max(table.strings.len() * 2);
+        }
+
+        let symbol: Arc<str> = Arc::from(s);
+        table.strings.insert(symbol.clone());
+        Symbol(symbol)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (Arc::as_ptr(&self.0) as *const u8 as usize).hash(state);
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialEq<Symbol> for String {
+    fn eq(&self, other: &Symbol) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl PartialEq<Symbol> for &str {
+    fn eq(&self, other: &Symbol) -> bool {
+        *self == other.as_str()
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(s: &str) -> Self {
+        Symbol::intern(s)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", &*self.0)
+    }
+}
+
+#[test]
+pub fn symbol_test() {
+    let a = Symbol::intern("name");
+    let b = Symbol::intern(&(String::from("na") + "me"));
+    assert!(Arc::ptr_eq(&a.0, &b.0));
+    assert!(a == b);
+    assert!(a != Symbol::intern("other"));
+    assert!(a == "name");
+
+    // Strings nobody holds get dropped from the table eventually but ones
+    // still in use keep their identity
+    let temp = Symbol::intern("temp0");
+    for i in 0..5000 {
+        Symbol::intern(&format!("temp{}", i));
+    }
+    let table_len = TABLE.lock().unwrap().strings.len();
+    assert!(table_len < 5000);
+    assert!(a == Symbol::intern("name"));
+    assert!(temp == Symbol::intern("temp0"));
+}
@@ -1,27 +1,108 @@
 use crate::scanner;
+use scanner::symbol::Symbol;
 use scanner::token_type;
+use std::collections::hash_map::DefaultHasher;
 use std::fmt;
+use std::hash::{Hash, Hasher};
+
+// Which text a span's offsets are into.  The REPL keeps functions from
+// earlier lines around so their spans can outlive the line they came from
+// and mustn't be drawn against whatever line is current.  0 means we don't
+// know.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct SourceId(u64);
+
+impl SourceId {
+    pub fn of(source: &str) -> Self {
+        let mut hasher = DefaultHasher::new();
+        source.hash(&mut hasher);
+        SourceId(hasher.finish())
+    }
+}
+
+// A stretch of the source as byte offsets - start inclusive, end exclusive
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub source: SourceId,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span {
+            start,
+            end,
+            source: SourceId::default(),
+        }
+    }
+
+    pub fn in_source(self, source: SourceId) -> Span {
+        Span { source, ..self }
+    }
+
+    // The smallest span covering both self and other
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+            source: self.source,
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct Token {
     pub ttype: token_type::TokenType,
-    pub lexeme: String,
+    pub lexeme: Symbol,
     // We can wrap up literal values in the TokenType enum
     pub line: usize,
+    // Counted in characters rather than bytes starting at 1.  Tokens that
+    // didn't come from the scanner have a column of 0.
+    pub column: usize,
+    // Where the lexeme is in the source
+    span: Span,
 }
 
 impl Token {
-    pub fn new(ttype: &token_type::TokenType, lexeme: &String, line: usize) -> Self {
+    pub fn new(ttype: &token_type::TokenType, lexeme: &str, line: usize) -> Self {
+        Self::new_at(ttype, lexeme, line, 0, Span::default())
+    }
+
+    pub fn new_at(
+        ttype: &token_type::TokenType,
+        lexeme: &str,
+        line: usize,
+        column: usize,
+        span: Span,
+    ) -> Self {
         Token {
             ttype: ttype.clone(),
-            lexeme: lexeme.clone(),
+            lexeme: Symbol::intern(lexeme),
             line,
+            column,
+            span,
         }
     }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    // Tokens we make up ourselves rather than scan don't point into the source
+    pub fn is_from_source(&self) -> bool {
+        self.column != 0
+    }
 }
 
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.write_str(format!("{}: {} [{}]", self.ttype, self.lexeme, self.line).as_ref())
+        f.write_str(
+            format!(
+                "{}: {} [{}:{}]",
+                self.ttype, self.lexeme, self.line, self.column
+            )
+            .as_ref(),
+        )
     }
 }
@@ -1,4 +1,7 @@
+use crate::scanner;
+
 use lazy_static::lazy_static;
+use scanner::symbol::Symbol;
 use std::collections::HashMap;
 use std::fmt;
 
@@ -17,6 +20,11 @@ pub enum TokenType {
     Semicolon,
     Slash,
     Star,
+    Percent,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
 
     // One or two character tokens
     Bang,
@@ -27,9 +35,13 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    TildeSlash,
+    StarStar,
+    LessLess,
+    GreaterGreater,
 
     // Literals
-    String(String),
+    String(Symbol),
     // We store the string for the float in Number - converting to an f64
     // causes TokenType to be unhashable which means we can't create our
     // hash tables below.  I tried using the enum discriminant but get the
@@ -40,7 +52,14 @@ pub enum TokenType {
     // would mean I'd lose the actual lexeme that led to the number so it's
     // a bit of a disadvantage and I decided to stick with the string.
     Number(String),
-    Identifier(String),
+    Identifier(Symbol),
+    // The part of an interpolated string up to a "${".  The expression comes
+    // next and then either another Interpolation or the String that finishes
+    // things off.
+    Interpolation(String),
+    // Only produced when the scanner's been asked to keep comments.  Holds
+    // the text between the delimiters - the lexeme has the whole thing.
+    Comment(String),
 
     // Keywords
     And,
@@ -65,6 +84,23 @@ pub enum TokenType {
     Error,
 }
 
+// What a number literal turns out to be.  Anything written with a fraction
+// or an exponent is a float and everything else is an integer.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NumberValue {
+    Integer(i64),
+    Float(f64),
+}
+
+impl NumberValue {
+    pub fn as_f64(self) -> f64 {
+        match self {
+            NumberValue::Integer(i) => i as f64,
+            NumberValue::Float(f) => f,
+        }
+    }
+}
+
 macro_rules! tt_entry {
     ($($type: tt: $val: expr) *) => (&[
         $((TokenType::$type, $val),)*
@@ -83,6 +119,11 @@ const TYPE_STRING: &'static [(TokenType, &str)] = tt_entry! {
     Semicolon: ";"
     Slash: "/"
     Star: "*"
+    Percent: "%"
+    Ampersand: "&"
+    Pipe: "|"
+    Caret: "^"
+    Tilde: "~"
     Bang: "!"
     BangEqual: "!="
     Equal: "="
@@ -91,6 +132,10 @@ const TYPE_STRING: &'static [(TokenType, &str)] = tt_entry! {
     GreaterEqual: ">="
     Less: "<"
     LessEqual: "<="
+    TildeSlash: "~/"
+    StarStar: "**"
+    LessLess: "<<"
+    GreaterGreater: ">>"
     And: "and"
     Class: "class"
     Else: "else"
@@ -100,6 +145,7 @@ const TYPE_STRING: &'static [(TokenType, &str)] = tt_entry! {
     If: "if"
     Nil: "nil"
     Or: "or"
+    Print: "print"
     Return: "return"
     Super: "super"
     This: "this"
@@ -135,13 +181,92 @@ lazy_static! {
 #[allow(dead_code)]
 impl TokenType {
     // We have to handle numeric value specially since including an f32 as an associated
-    // value in the enum renders it unhashable.
-    pub fn num_value(&self) -> f32 {
+    // value in the enum renders it unhashable.  The scanner reports Number
+    // tokens that don't parse and nothing runs after that so the unwrap is
+    // safe.
+    pub fn num_value(&self) -> NumberValue {
         match self {
-            Self::Number(text) => text.parse::<f32>().unwrap(),
+            Self::Number(text) => Self::parse_number(text).unwrap(),
             _ => {
                 assert!(false, "Trying to retrieve num value from non-num token");
-                0.0
+                NumberValue::Integer(0)
+            }
+        }
+    }
+
+    // Number literals can be decimal with an optional fraction and exponent,
+    // hex (0xff) or binary (0b1010).  Underscores can go between any two
+    // digits to make long numbers readable.  Errors are what the scanner
+    // reports.
+    pub fn parse_number(text: &str) -> Result<NumberValue, &'static str> {
+        let (digits, radix) = match text.get(..2) {
+            Some("0x") | Some("0X") => (&text[2..], 16),
+            Some("0b") | Some("0B") => (&text[2..], 2),
+            _ => (text, 10),
+        };
+
+        if digits.is_empty() {
+            return Err("Expected digits after number prefix.");
+        }
+
+        // Every underscore needs a digit on both sides
+        let chars: Vec<char> = digits.chars().collect();
+        for (i, c) in chars.iter().enumerate() {
+            if *c == '_' {
+                let before = i > 0 && chars[i - 1].is_digit(radix);
+                let after = chars.get(i + 1).is_some_and(|c| c.is_digit(radix));
+                if !before || !after {
+                    return Err("Misplaced '_' in number.");
+                }
+            }
+        }
+        let digits = digits.replace('_', "");
+
+        if radix == 10 {
+            // Rust would also take things like "inf" or "1e" without complaint
+            // if we let it so we check the shape ourselves first
+            let (mantissa, exponent) = match digits.find(['e', 'E']) {
+                Some(i) => (&digits[..i], Some(&digits[i + 1..])),
+                None => (&digits[..], None),
+            };
+            let valid_mantissa = mantissa.split_once('.').map_or(
+                mantissa.chars().all(|c| c.is_ascii_digit()),
+                |(w, f)| {
+                    w.chars().all(|c| c.is_ascii_digit())
+                        && !f.is_empty()
+                        && f.chars().all(|c| c.is_ascii_digit())
+                },
+            );
+            if !valid_mantissa {
+                return Err("Invalid number.");
+            }
+            if let Some(exponent) = exponent {
+                let exponent = exponent.trim_start_matches(['+', '-']);
+                if exponent.is_empty() || !exponent.chars().all(|c| c.is_ascii_digit()) {
+                    return Err("Invalid exponent in number.");
+                }
+            }
+            // Whole numbers too big for an integer are still fine as floats
+            if !digits.contains(['.', 'e', 'E']) {
+                if let Ok(value) = digits.parse::<i64>() {
+                    return Ok(NumberValue::Integer(value));
+                }
+            }
+            match digits.parse::<f64>() {
+                Ok(value) if value.is_infinite() => Err("Number is too large."),
+                Ok(value) => Ok(NumberValue::Float(value)),
+                Err(_) => Err("Invalid number."),
+            }
+        } else {
+            // Hex and binary spell out bits so all 64 of them are usable and
+            // 0xffff_ffff_ffff_ffff is -1
+            match u64::from_str_radix(&digits, radix) {
+                Ok(value) => Ok(NumberValue::Integer(value as i64)),
+                Err(e) if *e.kind() == std::num::IntErrorKind::PosOverflow => {
+                    Err("Number is too large.")
+                }
+                Err(_) if radix == 2 => Err("Invalid digit in binary number."),
+                Err(_) => Err("Invalid digit in hex number."),
             }
         }
     }
@@ -165,6 +290,8 @@ impl fmt::Display for TokenType {
             TokenType::String(s) => f.write_str(format!("\"{}\"", s).as_ref()),
             TokenType::Identifier(s) => f.write_str(format!("id[\"{}\"]", s).as_ref()),
             TokenType::Number(n) => f.write_str(format!("{}", n).as_ref()),
+            TokenType::Interpolation(s) => f.write_str(format!("\"{}${{\"", s).as_ref()),
+            TokenType::Comment(s) => f.write_str(format!("comment[\"{}\"]", s).as_ref()),
             // Everything else...
             _tt => f.write_str(MAP_TYPE_TO_STRING[_tt]),
         }
@@ -1,28 +1,176 @@
-use crate::colored::*;
 use crate::lox_error;
 use crate::parser;
-use crate::parser::evaluate::Evaluator;
+use crate::parser::evaluate::{Evaluator, STACK_SIZE};
+use crate::parser::resolver::Resolver;
 use crate::scanner::scanner;
+use crate::vm::bytecode_file::write_program;
+use crate::vm::compiler::Compiler;
+use crate::vm::machine::Vm;
+use crate::vm::value::{Heap, HeapStats};
 
 use lox_error::{lox_error::LoxError, lox_error::LoxErrorList};
-use parser::{parser::Parser, pretty_print::AstPrinter};
+use parser::parser::{Parser, Stmt};
 use std::env;
 use std::fs;
 use std::io::{self, stdout, BufRead, Write};
+use std::path::Path;
+use std::process;
+use std::thread;
 
+// The two ways we have of running a program once it's been through the
+// front end.  They have to behave identically.
+pub trait Backend {
+    fn interpret(&mut self, statements: &[Stmt]) -> LoxErrorList;
+    fn heap_stats(&self) -> HeapStats;
+}
+
+impl Backend for Evaluator {
+    fn interpret(&mut self, statements: &[Stmt]) -> LoxErrorList {
+        Evaluator::interpret(self, statements)
+    }
+
+    fn heap_stats(&self) -> HeapStats {
+        Evaluator::heap_stats(self)
+    }
+}
+
+impl Backend for Vm {
+    fn interpret(&mut self, statements: &[Stmt]) -> LoxErrorList {
+        Vm::interpret(self, statements)
+    }
+
+    fn heap_stats(&self) -> HeapStats {
+        Vm::heap_stats(self)
+    }
+}
+
+const SYNTAX: &str =
+    "Syntax: lox [--vm] [--disassemble] [--trace] [--stress-gc] [--gc-stats] [file]
+       lox compile file.lox [-o file.loxc]";
+
+// The main thread's stack isn't big enough for the evaluator so all the real
+// work happens on one that is
 pub fn compile() {
-    let args: Vec<String> = env::args().collect();
+    let worker = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(run_command)
+        .expect("Couldn't start the interpreter thread");
+    // A panic has already been reported by the time we get it back
+    if worker.join().is_err() {
+        process::exit(101);
+    }
+}
+
+fn run_command() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == "compile") {
+        return compile_command(&args[1..]);
+    }
 
-    if args.len() > 2 {
-        LoxError::new_text_only(None, "Syntax: lox [file]").report();
-    } else if args.len() == 2 {
-        run_file(&args[1])
+    // The bytecode debugging flags imply --vm.  Both backends collect
+    // garbage so the GC flags work with either.
+    let mut use_vm = false;
+    let mut disassemble = false;
+    let mut trace = false;
+    let mut stress_gc = false;
+    let mut gc_stats = false;
+    let mut files = vec![];
+    for arg in args {
+        match arg.as_str() {
+            "--vm" => use_vm = true,
+            "--disassemble" => disassemble = true,
+            "--trace" => trace = true,
+            "--stress-gc" => stress_gc = true,
+            "--gc-stats" => gc_stats = true,
+            flag if flag.starts_with("--") => {
+                LoxError::new_text_only(None, SYNTAX).report();
+                return;
+            }
+            _ => files.push(arg),
+        }
+    }
+
+    let new_vm = || {
+        let mut vm = Vm::new();
+        vm.set_disassemble(disassemble);
+        vm.set_trace(trace);
+        vm.set_stress_gc(stress_gc);
+        vm
+    };
+    // There's no tree left to walk in a compiled file so the VM has to run
+    // it whichever backend was asked for
+    let stats = if files.len() == 1 && files[0].ends_with(".loxc") {
+        let mut vm = new_vm();
+        run_compiled_file(&files[0], &mut vm);
+        vm.heap_stats()
     } else {
-        run_prompt();
+        let mut backend: Box<dyn Backend> = if use_vm || disassemble || trace {
+            Box::new(new_vm())
+        } else {
+            let mut evaluator = Evaluator::new();
+            evaluator.set_stress_gc(stress_gc);
+            Box::new(evaluator)
+        };
+        match files.len() {
+            0 => run_prompt(&mut *backend),
+            1 => run_file(&files[0], &mut *backend),
+            _ => return LoxError::new_text_only(None, SYNTAX).report(),
+        }
+        backend.heap_stats()
+    };
+
+    if gc_stats {
+        eprintln!(
+            "[gc] {} collections, {} objects allocated, {} freed, {} live (peak {})",
+            stats.collections, stats.allocated, stats.freed, stats.live, stats.peak
+        );
+    }
+}
+
+// Without -o the output goes next to the input with a .loxc extension
+fn compile_command(args: &[String]) {
+    let (input, output) = match args {
+        [input] => (input, Path::new(input).with_extension("loxc")),
+        [input, flag, output] if flag == "-o" => (input, Path::new(output).to_path_buf()),
+        _ => return LoxError::new_text_only(None, SYNTAX).report(),
+    };
+
+    let program = match fs::read_to_string(input) {
+        Ok(program) => program,
+        Err(_) => {
+            return LoxError::new_text_only(None, &format!("Couldn't read {}", input)).report()
+        }
+    };
+    let statements = match front_end(&program) {
+        Ok(statements) => statements,
+        Err(errors) => return errors.report_with_source(&program),
+    };
+
+    let mut heap = Heap::new();
+    let bytes = match Compiler::new(&mut heap).compile(&statements) {
+        Ok(function) => write_program(&heap, &function),
+        Err(errors) => return errors.report_with_source(&program),
+    };
+    let written = bytes.and_then(|bytes| {
+        fs::write(&output, bytes).map_err(|_| {
+            let msg = format!("Couldn't write {}", output.display());
+            LoxError::new_text_only(None, &msg)
+        })
+    });
+    if let Err(error) = written {
+        error.report();
     }
 }
 
-fn run_file(file: &String) {
+// Compiled files have no source to show alongside errors
+fn run_compiled_file(file: &str, vm: &mut Vm) {
+    match fs::read(file) {
+        Ok(bytes) => vm.run_compiled(&bytes).report(),
+        Err(_) => LoxError::new_text_only(None, &format!("Couldn't read {}", file)).report(),
+    }
+}
+
+fn run_file(file: &String, backend: &mut dyn Backend) {
     let program_val = fs::read_to_string(file);
     match program_val {
         Err(_) => {
@@ -30,12 +178,13 @@ fn run_file(file: &String) {
             error.report()
         }
         Ok(program) => {
-            run(&program).report();
+            run(&program, backend).report_with_source(&program);
         }
     }
 }
 
-fn run_prompt() {
+// One backend for the whole session so variables survive between lines
+fn run_prompt(backend: &mut dyn Backend) {
     let reader = io::stdin();
     println!("^c to end...\n");
     loop {
@@ -62,35 +211,42 @@ fn run_prompt() {
             }
             Ok(_) => line = line.trim().to_string(),
         };
-        run(&line).report()
+        run(&line, backend).report_with_source(&line)
     }
 }
 
 // run() should take care of all running (duh).  The only thing it's callers get is
 // a list of the errors.  The buck stops here.
-fn run(program: &String) -> LoxErrorList {
-    let scanner_test = scanner::Scanner::new(&program);
-    let mut scanner = match scanner_test {
-        Err(e) => return LoxErrorList::single(e.clone()),
-        Ok(s) => s,
-    };
+fn run(program: &str, backend: &mut dyn Backend) -> LoxErrorList {
+    match front_end(program) {
+        Ok(statements) => backend.interpret(&statements),
+        Err(errors) => errors,
+    }
+}
+
+// Scanning, parsing and resolving - everything up to the point where a
+// backend takes over
+pub fn front_end(program: &str) -> Result<Vec<Stmt>, LoxErrorList> {
+    let mut scanner = scanner::Scanner::new(program);
 
     scanner.scan_tokens();
+    let mut errors = scanner.get_errors();
 
     // This kills scanner as it moves all the tokens out of it - they now belong to
-    // the parser
+    // the parser.  We still parse after lexical errors so any syntax errors get
+    // reported too but nothing runs.
     let mut parser = Parser::new(scanner.get_tokens());
-    let expr_opt = parser.parse();
-    match expr_opt {
-        None => parser.errors,
-        Some(ast) => {
-            let mut printer = AstPrinter {};
-            println!("{}", printer.pretty_print_value(&*ast).yellow());
-            let mut errors = parser.errors;
+    let statements_opt = parser.parse();
+    errors.append(parser.errors);
+    match statements_opt {
+        Some(statements) if errors.len() == 0 => {
+            let errors = Resolver::new().resolve(&statements);
             if errors.len() == 0 {
-                errors = Evaluator {}.interpret(&*ast);
+                Ok(statements)
+            } else {
+                Err(errors)
             }
-            errors
         }
+        _ => Err(errors),
     }
 }
@@ -0,0 +1,656 @@
+use crate::lox_error;
+use crate::scanner;
+use crate::vm;
+
+use lox_error::lox_error::LoxError;
+use scanner::symbol::Symbol;
+use scanner::token::{Span, Token};
+use scanner::token_type::TokenType;
+use std::convert::TryFrom;
+use std::rc::Rc;
+use vm::chunk::{Chunk, ErrorSite, OpCode};
+use vm::value::{Function, Heap, Obj, Value};
+
+// Layout of a .loxc file:
+//
+//   "LOXC"        magic
+//   u16           FORMAT_VERSION
+//   u32           CRC-32 of everything after it
+//   function      the top level script
+//
+// A function is its name, arity, upvalue count and chunk.  A chunk is its
+// code, a line for every byte of code, its constants and its error sites.
+// Numbers are little endian, lengths and offsets are u32s and strings are
+// a length followed by UTF-8.
+//
+// Bump FORMAT_VERSION whenever the layout or the meaning of any opcode
+// changes so old files get turned away instead of misbehaving.
+const MAGIC: &[u8] = b"LOXC";
+pub const FORMAT_VERSION: u16 = 1;
+const HEADER_LEN: usize = 10;
+
+// Constant tags
+const NUMBER: u8 = 0;
+const INTEGER: u8 = 1;
+const STRING: u8 = 2;
+const FUNCTION: u8 = 3;
+
+pub fn write_program(heap: &Heap, function: &Function) -> Result<Vec<u8>, LoxError> {
+    let mut writer = Writer { bytes: vec![] };
+    writer.function(heap, function)?;
+
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&crc32(&writer.bytes).to_le_bytes());
+    bytes.extend_from_slice(&writer.bytes);
+    Ok(bytes)
+}
+
+// Strings and functions from the file end up in the heap
+pub fn read_program(heap: &mut Heap, bytes: &[u8]) -> Result<Rc<Function>, LoxError> {
+    if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC {
+        return Err(error("Not a compiled Lox file."));
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != FORMAT_VERSION {
+        let msg = format!(
+            "Compiled file has format version {} but this lox reads version {}.  Recompile it from the source.",
+            version, FORMAT_VERSION
+        );
+        return Err(error(&msg));
+    }
+    let checksum = u32::from_le_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]);
+    if crc32(&bytes[HEADER_LEN..]) != checksum {
+        return Err(error("Compiled file is corrupt (checksum mismatch)."));
+    }
+
+    let mut reader = Reader {
+        bytes: &bytes[HEADER_LEN..],
+        position: 0,
+    };
+    let function = reader.function(heap)?;
+    // The script is called with no arguments and doesn't capture anything
+    if reader.position != reader.bytes.len() || function.arity != 0 || function.upvalue_count != 0 {
+        return Err(corrupt());
+    }
+    Ok(Rc::new(function))
+}
+
+fn error(msg: &str) -> LoxError {
+    LoxError::new_text_only(None, msg)
+}
+
+fn corrupt() -> LoxError {
+    error("Compiled file is corrupt.")
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn function(&mut self, heap: &Heap, function: &Function) -> Result<(), LoxError> {
+        self.string(&function.name)?;
+        self.length(function.arity)?;
+        self.length(function.upvalue_count)?;
+        self.chunk(heap, &function.chunk)
+    }
+
+    fn chunk(&mut self, heap: &Heap, chunk: &Chunk) -> Result<(), LoxError> {
+        self.length(chunk.code.len())?;
+        self.bytes.extend_from_slice(&chunk.code);
+        for line in &chunk.lines {
+            self.length(*line)?;
+        }
+
+        self.length(chunk.constants.len())?;
+        for constant in &chunk.constants {
+            self.constant(heap, *constant)?;
+        }
+
+        self.length(chunk.sites.len())?;
+        for site in &chunk.sites {
+            self.length(site.offset)?;
+            self.token(&site.token)?;
+            self.span(site.span)?;
+            self.span(site.operand_span)?;
+        }
+        Ok(())
+    }
+
+    // The compiler only ever makes numbers, strings and functions constants
+    fn constant(&mut self, heap: &Heap, constant: Value) -> Result<(), LoxError> {
+        match constant {
+            Value::Number(n) => {
+                self.bytes.push(NUMBER);
+                self.bytes.extend_from_slice(&n.to_bits().to_le_bytes());
+            }
+            Value::Integer(i) => {
+                self.bytes.push(INTEGER);
+                self.bytes.extend_from_slice(&i.to_le_bytes());
+            }
+            Value::Obj(obj) => match heap.get(obj) {
+                Obj::String(s) => {
+                    self.bytes.push(STRING);
+                    self.string(s)?;
+                }
+                Obj::Function(function) => {
+                    self.bytes.push(FUNCTION);
+                    self.function(heap, function)?;
+                }
+                _ => return Err(error("Can't save this constant in a compiled file.")),
+            },
+            _ => return Err(error("Can't save this constant in a compiled file.")),
+        }
+        Ok(())
+    }
+
+    // Only identifiers need their type spelled out.  Everything else an
+    // error can point at is a keyword or operator which its lexeme gives us.
+    fn token(&mut self, token: &Token) -> Result<(), LoxError> {
+        let is_identifier = matches!(token.ttype, TokenType::Identifier(_));
+        self.bytes.push(is_identifier as u8);
+        self.string(&token.lexeme)?;
+        self.length(token.line)?;
+        self.length(token.column)?;
+        self.span(token.span())
+    }
+
+    fn span(&mut self, span: Span) -> Result<(), LoxError> {
+        self.length(span.start)?;
+        self.length(span.end)
+    }
+
+    fn string(&mut self, s: &str) -> Result<(), LoxError> {
+        self.length(s.len())?;
+        self.bytes.extend_from_slice(s.as_bytes());
+        Ok(())
+    }
+
+    fn length(&mut self, n: usize) -> Result<(), LoxError> {
+        let n = u32::try_from(n).map_err(|_| error("Program too large to save."))?;
+        self.bytes.extend_from_slice(&n.to_le_bytes());
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn function(&mut self, heap: &mut Heap) -> Result<Function, LoxError> {
+        let name = self.string()?;
+        let arity = self.length()?;
+        let upvalue_count = self.length()?;
+        let chunk = self.chunk(heap)?;
+        let function = Function {
+            name,
+            arity,
+            upvalue_count,
+            chunk,
+        };
+        check_code(heap, &function)?;
+        Ok(function)
+    }
+
+    fn chunk(&mut self, heap: &mut Heap) -> Result<Chunk, LoxError> {
+        let mut chunk = Chunk::new();
+        let code_len = self.length()?;
+        chunk.code = self.take(code_len)?.to_vec();
+        for _ in 0..code_len {
+            chunk.lines.push(self.length()?);
+        }
+
+        let constant_count = self.length()?;
+        for _ in 0..constant_count {
+            let constant = self.constant(heap)?;
+            chunk.add_constant(constant);
+        }
+
+        let site_count = self.length()?;
+        for _ in 0..site_count {
+            let site = ErrorSite {
+                offset: self.length()?,
+                token: self.token()?,
+                span: self.span()?,
+                operand_span: self.span()?,
+            };
+            // Chunk::site() relies on these being in order
+            if chunk
+                .sites
+                .last()
+                .is_some_and(|last| last.offset >= site.offset)
+            {
+                return Err(corrupt());
+            }
+            chunk.add_site(site);
+        }
+        Ok(chunk)
+    }
+
+    fn constant(&mut self, heap: &mut Heap) -> Result<Value, LoxError> {
+        match self.take(1)?[0] {
+            NUMBER => Ok(Value::Number(f64::from_bits(self.u64()?))),
+            INTEGER => Ok(Value::Integer(self.u64()? as i64)),
+            STRING => {
+                let s = self.string()?;
+                Ok(heap.alloc_string(&s))
+            }
+            FUNCTION => {
+                let function = self.function(heap)?;
+                Ok(Value::Obj(heap.alloc(Obj::Function(Rc::new(function)))))
+            }
+            _ => Err(corrupt()),
+        }
+    }
+
+    fn token(&mut self) -> Result<Token, LoxError> {
+        let is_identifier = self.take(1)?[0] == 1;
+        let lexeme = self.string()?;
+        let ttype = if is_identifier {
+            TokenType::Identifier(Symbol::intern(&lexeme))
+        } else {
+            TokenType::to_keyword(&lexeme).ok_or_else(corrupt)?
+        };
+        let line = self.length()?;
+        let column = self.length()?;
+        let span = self.span()?;
+        Ok(Token::new_at(&ttype, &lexeme, line, column, span))
+    }
+
+    fn span(&mut self) -> Result<Span, LoxError> {
+        Ok(Span::new(self.length()?, self.length()?))
+    }
+
+    fn string(&mut self) -> Result<String, LoxError> {
+        let len = self.length()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| corrupt())
+    }
+
+    fn length(&mut self) -> Result<usize, LoxError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    }
+
+    fn u64(&mut self) -> Result<u64, LoxError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], LoxError> {
+        let end = self.position.checked_add(len).ok_or_else(corrupt)?;
+        let bytes = self.bytes.get(self.position..end).ok_or_else(corrupt)?;
+        self.position = end;
+        Ok(bytes)
+    }
+}
+
+// The checksum only catches accidents so this makes sure a damaged or
+// hand made file can't have the VM read past the end of the code, the
+// constants, the stack or the closure's upvalues.  Beyond that compiled
+// files are trusted like source is.
+fn check_code(heap: &Heap, function: &Function) -> Result<(), LoxError> {
+    let chunk = &function.chunk;
+    let code = &chunk.code;
+
+    // Every instruction has to decode and the last has to be a Return so
+    // the only way off the end is a bad jump
+    let mut starts = vec![false; code.len()];
+    let mut offset = 0;
+    let mut last = None;
+    while offset < code.len() {
+        starts[offset] = true;
+        let op = OpCode::from_byte(code[offset]).ok_or_else(corrupt)?;
+        offset += instruction_len(heap, chunk, offset, op)?;
+        last = Some(op);
+    }
+    if last != Some(OpCode::Return) {
+        return Err(corrupt());
+    }
+
+    // Then every path through the code, keeping track of how many values
+    // the function has on the stack and which of them closures might have
+    // captured.  It starts with slot 0 and the arguments.  Paths that meet
+    // have to agree on the height, just like they always do in compiled
+    // code, but a loop can capture something the first time round that it
+    // hadn't before so captures are merged and the code gone over again.
+    let mut states: Vec<Option<(usize, Vec<usize>)>> = vec![None; code.len()];
+    let mut pending = vec![(0, 1 + function.arity, vec![])];
+    while let Some((offset, height, mut captured)) = pending.pop() {
+        if !starts.get(offset).copied().unwrap_or(false) {
+            return Err(corrupt());
+        }
+        match &mut states[offset] {
+            Some((seen, _)) if *seen != height => return Err(corrupt()),
+            Some((_, seen)) => {
+                if captured.iter().all(|slot| seen.contains(slot)) {
+                    continue;
+                }
+                for slot in captured {
+                    if !seen.contains(&slot) {
+                        seen.push(slot);
+                    }
+                }
+                captured = seen.clone();
+            }
+            None => states[offset] = Some((height, captured.clone())),
+        }
+
+        let op = OpCode::from_byte(code[offset]).ok_or_else(corrupt)?;
+        let next = offset + instruction_len(heap, chunk, offset, op)?;
+        // Only means anything for the instructions with a u16 operand
+        let operand = if next - offset >= 3 {
+            chunk.read_u16(offset + 1) as usize
+        } else {
+            0
+        };
+        let local = |slot: usize| {
+            if slot < height {
+                Ok(())
+            } else {
+                Err(corrupt())
+            }
+        };
+        let upvalue = |index: usize| {
+            if index < function.upvalue_count {
+                Ok(())
+            } else {
+                Err(corrupt())
+            }
+        };
+
+        // How many values the instruction takes off the stack and how many
+        // it puts back
+        let (pops, pushes) = match op {
+            OpCode::Constant
+            | OpCode::Nil
+            | OpCode::True
+            | OpCode::False
+            | OpCode::GetGlobal
+            | OpCode::Class => (0, 1),
+            OpCode::GetLocal => {
+                local(operand)?;
+                (0, 1)
+            }
+            OpCode::SetLocal => {
+                local(operand)?;
+                (1, 1)
+            }
+            OpCode::GetUpvalue => {
+                upvalue(operand)?;
+                (0, 1)
+            }
+            OpCode::SetUpvalue => {
+                upvalue(operand)?;
+                (1, 1)
+            }
+            // A local function captures itself - the slot the closure is
+            // about to be pushed into
+            OpCode::Closure => {
+                for capture in (offset + 3..next).step_by(3) {
+                    let index = chunk.read_u16(capture + 1) as usize;
+                    if code[capture] == 1 {
+                        if index > height {
+                            return Err(corrupt());
+                        }
+                        if !captured.contains(&index) {
+                            captured.push(index);
+                        }
+                    } else {
+                        upvalue(index)?;
+                    }
+                }
+                (0, 1)
+            }
+            OpCode::Pop | OpCode::DefineGlobal | OpCode::Print | OpCode::CloseUpvalue => (1, 0),
+            OpCode::SetGlobal
+            | OpCode::GetProperty
+            | OpCode::CheckInstance
+            | OpCode::Not
+            | OpCode::Negate
+            | OpCode::BitNot
+            | OpCode::JumpIfFalse => (1, 1),
+            OpCode::Jump | OpCode::Loop => (0, 0),
+            OpCode::Interpolate => (operand, 1),
+            OpCode::Call => (code[offset + 1] as usize + 1, 1),
+            OpCode::Return => (1, 0),
+            // The binary operators, SetProperty and the class building ones
+            _ => (2, 1),
+        };
+        if pops > height {
+            return Err(corrupt());
+        }
+        let height = height - pops + pushes;
+
+        // A captured variable's upvalue reads it from the stack until it's
+        // closed so only CloseUpvalue and Return, which close it first, may
+        // take it off
+        match op {
+            OpCode::CloseUpvalue => captured.retain(|&slot| slot < height),
+            OpCode::Return => (),
+            _ if captured.iter().any(|&slot| slot >= height) => return Err(corrupt()),
+            _ => (),
+        }
+
+        match op {
+            OpCode::Return => (),
+            OpCode::Jump => pending.push((next + operand, height, captured)),
+            OpCode::Loop => {
+                let target = next.checked_sub(operand).ok_or_else(corrupt)?;
+                pending.push((target, height, captured));
+            }
+            OpCode::JumpIfFalse => {
+                pending.push((next + operand, height, captured.clone()));
+                pending.push((next, height, captured));
+            }
+            _ => pending.push((next, height, captured)),
+        }
+    }
+    Ok(())
+}
+
+// Also checks the operands are all there and any constant they refer to is
+// the right sort
+fn instruction_len(
+    heap: &Heap,
+    chunk: &Chunk,
+    offset: usize,
+    op: OpCode,
+) -> Result<usize, LoxError> {
+    let code = &chunk.code;
+    let u16_at = |offset: usize| -> Result<usize, LoxError> {
+        match code.get(offset..offset + 2) {
+            Some(bytes) => Ok(((bytes[0] as usize) << 8) | bytes[1] as usize),
+            None => Err(corrupt()),
+        }
+    };
+    let constant = |index: usize| chunk.constants.get(index).copied().ok_or_else(corrupt);
+
+    match op {
+        OpCode::Constant => {
+            constant(u16_at(offset + 1)?)?;
+            Ok(3)
+        }
+        OpCode::GetGlobal
+        | OpCode::DefineGlobal
+        | OpCode::SetGlobal
+        | OpCode::GetProperty
+        | OpCode::SetProperty
+        | OpCode::GetSuper
+        | OpCode::Class
+        | OpCode::Method => {
+            let name = constant(u16_at(offset + 1)?)?;
+            heap.as_str(name).ok_or_else(corrupt)?;
+            Ok(3)
+        }
+        OpCode::GetLocal
+        | OpCode::SetLocal
+        | OpCode::GetUpvalue
+        | OpCode::SetUpvalue
+        | OpCode::Interpolate
+        | OpCode::Jump
+        | OpCode::JumpIfFalse
+        | OpCode::Loop => {
+            u16_at(offset + 1)?;
+            Ok(3)
+        }
+        OpCode::Call => {
+            code.get(offset + 1).ok_or_else(corrupt)?;
+            Ok(2)
+        }
+        OpCode::Closure => {
+            let upvalue_count = match constant(u16_at(offset + 1)?)? {
+                Value::Obj(obj) => match heap.get(obj) {
+                    Obj::Function(function) => function.upvalue_count,
+                    _ => return Err(corrupt()),
+                },
+                _ => return Err(corrupt()),
+            };
+            for i in 0..upvalue_count {
+                u16_at(offset + 4 + i * 3)?;
+            }
+            Ok(3 + upvalue_count * 3)
+        }
+        _ => Ok(1),
+    }
+}
+
+// The usual CRC-32 as used by zip and PNG
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+use crate::parser::evaluate::{run_program, SharedBuffer};
+#[cfg(test)]
+use crate::setup::compile::front_end;
+#[cfg(test)]
+use crate::vm::compiler::Compiler;
+#[cfg(test)]
+use crate::vm::machine::Vm;
+#[cfg(test)]
+use std::cell::RefCell;
+
+#[cfg(test)]
+fn compile_to_bytes(program: &str) -> Vec<u8> {
+    let statements = front_end(program).ok().unwrap();
+    let mut heap = Heap::new();
+    let function = Compiler::new(&mut heap).compile(&statements).ok().unwrap();
+    write_program(&heap, &function).ok().unwrap()
+}
+
+#[test]
+pub fn round_trip_test() {
+    let program = "
+        fun counter() { var n = 0; fun inc() { n = n + 1; return n; } return inc; }
+        var c = counter(); c(); print c();
+        class A { init(x) { this.x = x; } }
+        class B < A { show() { print \"x=${this.x} ${1.5 * 2} ${7 ~/ 2}\"; } }
+        B(42).show();
+        print nil.field;";
+    let bytes = compile_to_bytes(program);
+
+    let buffer = Rc::new(RefCell::new(vec![]));
+    let mut vm = Vm::with_output(Box::new(SharedBuffer(buffer.clone())));
+    let errors = vm.run_compiled(&bytes);
+    let output = String::from_utf8(buffer.borrow().clone()).unwrap();
+
+    // Errors still point at the right place without the source around
+    let (expected_output, expected_errors) = run_program(program);
+    assert_eq!(expected_output, output);
+    assert_eq!(1, errors.len());
+    assert_eq!(
+        expected_errors.iter().next().unwrap().report_msg(),
+        errors.iter().next().unwrap().report_msg()
+    );
+}
+
+#[test]
+pub fn bad_file_test() {
+    let message = |bytes: &[u8]| match read_program(&mut Heap::new(), bytes) {
+        Ok(_) => String::new(),
+        Err(error) => error.report_msg(),
+    };
+    let bytes = compile_to_bytes("print 1;");
+
+    let mut other_version = bytes.clone();
+    other_version[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+    let expected = format!(
+        "Compiled file has format version {} but this lox reads version {}.  Recompile it from the source.",
+        FORMAT_VERSION + 1,
+        FORMAT_VERSION
+    );
+    assert_eq!(expected, message(&other_version));
+
+    let mut damaged = bytes.clone();
+    let last = damaged.len() - 1;
+    damaged[last] ^= 0xff;
+    assert_eq!(
+        "Compiled file is corrupt (checksum mismatch).",
+        message(&damaged)
+    );
+
+    assert_eq!("Not a compiled Lox file.", message(b"print 1;"));
+    assert_eq!("", message(&bytes));
+
+    // Code that would send the VM somewhere it shouldn't go, with the
+    // checksum fixed up so only the code checks can catch it.  The
+    // script's code starts after the header, its name, arity, upvalue
+    // count and code length.
+    let bytes = compile_to_bytes("class A {}");
+    let code_start = HEADER_LEN + 16;
+    let mut code_len = [0; 4];
+    code_len.copy_from_slice(&bytes[code_start - 4..code_start]);
+    let code_len = u32::from_le_bytes(code_len) as usize;
+    let with_code = |offset: usize, code: &[u8]| {
+        let mut bytes = bytes.clone();
+        bytes[code_start + offset..code_start + offset + code.len()].copy_from_slice(code);
+        let checksum = crc32(&bytes[HEADER_LEN..]);
+        bytes[6..HEADER_LEN].copy_from_slice(&checksum.to_le_bytes());
+        bytes
+    };
+    let bad_code = [
+        (0, vec![OpCode::Jump as u8, 0xff, 0xff]),
+        (0, vec![OpCode::Jump as u8, 0, 1]),
+        (0, vec![OpCode::Loop as u8, 0x10, 0]),
+        (0, vec![OpCode::GetLocal as u8, 0xff, 0xff]),
+        (0, vec![OpCode::GetUpvalue as u8, 0, 0]),
+        (
+            0,
+            vec![OpCode::Pop as u8, OpCode::Pop as u8, OpCode::Pop as u8],
+        ),
+        (code_len - 1, vec![OpCode::Nil as u8]),
+    ];
+    for (offset, code) in bad_code.iter() {
+        assert_eq!(
+            "Compiled file is corrupt.",
+            message(&with_code(*offset, code)),
+            "{:?}",
+            code
+        );
+    }
+    assert_eq!("", message(&with_code(0, &[OpCode::Nil as u8; 3])));
+
+    // Popping a captured variable instead of closing it would leave its
+    // upvalue pointing past the top of the stack
+    let mut bytes = compile_to_bytes("{ var x = 1; fun g() { return x; } }");
+    let close = code_start + 10;
+    assert_eq!(OpCode::CloseUpvalue as u8, bytes[close]);
+    assert_eq!("", message(&bytes));
+    bytes[close] = OpCode::Pop as u8;
+    let checksum = crc32(&bytes[HEADER_LEN..]);
+    bytes[6..HEADER_LEN].copy_from_slice(&checksum.to_le_bytes());
+    assert_eq!("Compiled file is corrupt.", message(&bytes));
+}
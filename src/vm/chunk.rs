@@ -0,0 +1,192 @@
+use crate::scanner;
+use crate::vm;
+
+use scanner::token::{Span, Token};
+use vm::value::Value;
+
+// Operands follow the opcode in the code stream.  Constant, variable and
+// property indices are u16s, Call's argument count is a u8 and jumps are
+// u16 offsets from the end of the jump instruction.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+pub enum OpCode {
+    Constant,
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal,
+    SetLocal,
+    GetGlobal,
+    DefineGlobal,
+    SetGlobal,
+    GetUpvalue,
+    SetUpvalue,
+    GetProperty,
+    SetProperty,
+    // Makes sure what's on top of the stack can have fields set on it
+    // before the value being assigned is evaluated
+    CheckInstance,
+    GetSuper,
+    Equal,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    IntDivide,
+    Modulo,
+    Power,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+    Not,
+    Negate,
+    BitNot,
+    // Operand is the number of parts to pop and join into one string
+    Interpolate,
+    Print,
+    Jump,
+    JumpIfFalse,
+    Loop,
+    Call,
+    // Operand is the function constant followed by an (is_local: u8,
+    // index: u16) pair for each upvalue it captures
+    Closure,
+    CloseUpvalue,
+    Return,
+    Class,
+    Inherit,
+    Method,
+}
+
+// Lets us get from a byte back to an opcode without any unsafe.  Order
+// matters - it has to list the opcodes exactly as they're declared.
+const OPCODES: &[OpCode] = &[
+    OpCode::Constant,
+    OpCode::Nil,
+    OpCode::True,
+    OpCode::False,
+    OpCode::Pop,
+    OpCode::GetLocal,
+    OpCode::SetLocal,
+    OpCode::GetGlobal,
+    OpCode::DefineGlobal,
+    OpCode::SetGlobal,
+    OpCode::GetUpvalue,
+    OpCode::SetUpvalue,
+    OpCode::GetProperty,
+    OpCode::SetProperty,
+    OpCode::CheckInstance,
+    OpCode::GetSuper,
+    OpCode::Equal,
+    OpCode::Greater,
+    OpCode::GreaterEqual,
+    OpCode::Less,
+    OpCode::LessEqual,
+    OpCode::Add,
+    OpCode::Subtract,
+    OpCode::Multiply,
+    OpCode::Divide,
+    OpCode::IntDivide,
+    OpCode::Modulo,
+    OpCode::Power,
+    OpCode::BitAnd,
+    OpCode::BitOr,
+    OpCode::BitXor,
+    OpCode::ShiftLeft,
+    OpCode::ShiftRight,
+    OpCode::Not,
+    OpCode::Negate,
+    OpCode::BitNot,
+    OpCode::Interpolate,
+    OpCode::Print,
+    OpCode::Jump,
+    OpCode::JumpIfFalse,
+    OpCode::Loop,
+    OpCode::Call,
+    OpCode::Closure,
+    OpCode::CloseUpvalue,
+    OpCode::Return,
+    OpCode::Class,
+    OpCode::Inherit,
+    OpCode::Method,
+];
+
+impl OpCode {
+    pub fn from_byte(byte: u8) -> Option<OpCode> {
+        OPCODES.get(byte as usize).copied()
+    }
+}
+
+// Where to point if the instruction at offset fails at runtime.  token and
+// span are what the tree walker would have reported.  operand_span covers
+// the callee of a call or the object of a property access for the errors
+// that are about them rather than the whole expression.
+#[derive(Clone)]
+pub struct ErrorSite {
+    pub offset: usize,
+    pub token: Token,
+    pub span: Span,
+    pub operand_span: Span,
+}
+
+// A compiled function body.  lines has an entry for every byte of code.
+// Only instructions that can fail get an ErrorSite and they're added in
+// order of offset.
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Value>,
+    pub lines: Vec<usize>,
+    pub sites: Vec<ErrorSite>,
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Chunk {
+            code: vec![],
+            constants: vec![],
+            lines: vec![],
+            sites: vec![],
+        }
+    }
+
+    pub fn write(&mut self, byte: u8, line: usize) {
+        self.code.push(byte);
+        self.lines.push(line);
+    }
+
+    pub fn write_op(&mut self, op: OpCode, line: usize) {
+        self.write(op as u8, line);
+    }
+
+    pub fn write_u16(&mut self, value: u16, line: usize) {
+        self.write((value >> 8) as u8, line);
+        self.write((value & 0xff) as u8, line);
+    }
+
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        ((self.code[offset] as u16) << 8) | self.code[offset + 1] as u16
+    }
+
+    pub fn add_constant(&mut self, value: Value) -> usize {
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+
+    pub fn add_site(&mut self, site: ErrorSite) {
+        self.sites.push(site);
+    }
+
+    pub fn site(&self, offset: usize) -> Option<&ErrorSite> {
+        self.sites
+            .binary_search_by_key(&offset, |site| site.offset)
+            .ok()
+            .map(|i| &self.sites[i])
+    }
+}
@@ -0,0 +1,704 @@
+use crate::lox_error;
+use crate::parser;
+use crate::scanner;
+use crate::vm;
+
+use lox_error::lox_error::{LoxError, LoxErrorList};
+use parser::parser::pstructs::{
+    assign, binary, call, get, grouping, interpolation, literal, logical, set, super_expr, this,
+    unary, variable, Accept, Visitor,
+};
+use parser::parser::{ParseReturn, Stmt};
+use parser::statement::sstructs;
+use scanner::token::{Span, Token};
+use scanner::token_type::TokenType;
+use std::convert::TryFrom;
+use std::rc::Rc;
+use vm::chunk::{ErrorSite, OpCode};
+use vm::value::{Function, Heap, Obj, Value};
+
+// What sort of function body we're compiling.  Methods and initializers
+// keep "this" in slot 0 and initializers always return it.
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionType {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+struct Local {
+    name: String,
+    depth: usize,
+    // Captured locals have to be moved off the stack when they go out of scope
+    is_captured: bool,
+}
+
+// Where a closure finds a captured variable when it's created - either a
+// local of the function around it or one of that function's own upvalues
+#[derive(Clone, Copy, PartialEq)]
+struct UpvalueRef {
+    index: u16,
+    is_local: bool,
+}
+
+// Everything we need to keep track of for one function while it's being
+// compiled.  Functions nest so the compiler keeps a stack of these.
+struct FunctionState {
+    function: Function,
+    ftype: FunctionType,
+    locals: Vec<Local>,
+    upvalues: Vec<UpvalueRef>,
+    scope_depth: usize,
+}
+
+// Turns a resolved AST into bytecode for the VM.  It runs after the
+// resolver so anything the resolver would complain about never gets here.
+// The only errors of our own are running into the limits of the bytecode.
+// Locals are worked out again here rather than taken from the resolver's
+// depths since the VM needs stack slots rather than environment distances.
+pub struct Compiler<'a> {
+    heap: &'a mut Heap,
+    functions: Vec<FunctionState>,
+    // The line of the last token we saw, for the line table
+    line: usize,
+    errors: LoxErrorList,
+}
+
+impl<'a> Compiler<'a> {
+    pub fn new(heap: &'a mut Heap) -> Self {
+        Compiler {
+            heap,
+            functions: vec![],
+            line: 1,
+            errors: LoxErrorList::new(),
+        }
+    }
+
+    pub fn compile(mut self, statements: &[Stmt]) -> Result<Rc<Function>, LoxErrorList> {
+        self.begin_function("", FunctionType::Script);
+        for stmt in statements {
+            self.statement(&**stmt);
+        }
+        let (function, _) = self.end_function();
+
+        if self.errors.len() == 0 {
+            Ok(Rc::new(function))
+        } else {
+            Err(self.errors)
+        }
+    }
+
+    fn statement(&mut self, stmt: &(dyn sstructs::Accept + 'static)) {
+        let _ = stmt.accept(self);
+    }
+
+    fn expression(&mut self, expr: &(dyn Accept + 'static)) {
+        let _ = expr.accept(self);
+    }
+
+    /////////////////////////////////////////////////////////////////////////
+    // Functions
+    /////////////////////////////////////////////////////////////////////////
+    fn begin_function(&mut self, name: &str, ftype: FunctionType) {
+        // Slot 0 holds the function being called.  In methods that's where
+        // the receiver goes so it's named "this" and can be looked up.
+        let slot_zero = match ftype {
+            FunctionType::Method | FunctionType::Initializer => "this",
+            _ => "",
+        };
+        self.functions.push(FunctionState {
+            function: Function {
+                name: name.to_string(),
+                arity: 0,
+                upvalue_count: 0,
+                chunk: vm::chunk::Chunk::new(),
+            },
+            ftype,
+            locals: vec![Local {
+                name: slot_zero.to_string(),
+                depth: 0,
+                is_captured: false,
+            }],
+            upvalues: vec![],
+            scope_depth: if ftype == FunctionType::Script { 0 } else { 1 },
+        });
+    }
+
+    fn end_function(&mut self) -> (Function, Vec<UpvalueRef>) {
+        self.emit_return();
+        let state = self.functions.pop().unwrap();
+        let mut function = state.function;
+        function.upvalue_count = state.upvalues.len();
+        (function, state.upvalues)
+    }
+
+    fn function_body(&mut self, stmt: &sstructs::function, ftype: FunctionType) {
+        self.line = stmt.name.line;
+        self.begin_function(&stmt.name.lexeme, ftype);
+        for param in &stmt.params {
+            self.add_local(&param.lexeme);
+        }
+        self.current().function.arity = stmt.params.len();
+        for body_stmt in stmt.body.iter() {
+            self.statement(&**body_stmt);
+        }
+        let (function, upvalues) = self.end_function();
+
+        self.line = stmt.name.line;
+        let function = self.heap.alloc(Obj::Function(Rc::new(function)));
+        let constant = self.make_constant(Value::Obj(function));
+        self.emit_op(OpCode::Closure);
+        self.emit_u16(constant);
+        for upvalue in upvalues {
+            self.emit_byte(upvalue.is_local as u8);
+            self.emit_u16(upvalue.index);
+        }
+    }
+
+    fn emit_return(&mut self) {
+        if self.current().ftype == FunctionType::Initializer {
+            self.emit_op(OpCode::GetLocal);
+            self.emit_u16(0);
+        } else {
+            self.emit_op(OpCode::Nil);
+        }
+        self.emit_op(OpCode::Return);
+    }
+
+    /////////////////////////////////////////////////////////////////////////
+    // Variables and scopes
+    /////////////////////////////////////////////////////////////////////////
+    fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.current().scope_depth -= 1;
+        loop {
+            let state = self.current();
+            let captured = match state.locals.last() {
+                Some(local) if local.depth > state.scope_depth => local.is_captured,
+                _ => break,
+            };
+            state.locals.pop();
+            self.emit_op(if captured {
+                OpCode::CloseUpvalue
+            } else {
+                OpCode::Pop
+            });
+        }
+    }
+
+    fn add_local(&mut self, name: &str) {
+        if self.current().locals.len() > u16::MAX as usize {
+            self.error("Too many local variables in function.");
+            return;
+        }
+        let depth = self.current().scope_depth;
+        self.current().locals.push(Local {
+            name: name.to_string(),
+            depth,
+            is_captured: false,
+        });
+    }
+
+    fn resolve_local(&mut self, function: usize, name: &str) -> Option<u16> {
+        let locals = &self.functions[function].locals;
+        (0..locals.len())
+            .rev()
+            .find(|&slot| locals[slot].name == name)
+            .map(|slot| slot as u16)
+    }
+
+    // Looks for the variable in the functions enclosing this one, capturing
+    // it through each function in between on the way back
+    fn resolve_upvalue(&mut self, function: usize, name: &str) -> Option<u16> {
+        if function == 0 {
+            return None;
+        }
+
+        if let Some(slot) = self.resolve_local(function - 1, name) {
+            self.functions[function - 1].locals[slot as usize].is_captured = true;
+            return Some(self.add_upvalue(function, slot, true));
+        }
+
+        let index = self.resolve_upvalue(function - 1, name)?;
+        Some(self.add_upvalue(function, index, false))
+    }
+
+    fn add_upvalue(&mut self, function: usize, index: u16, is_local: bool) -> u16 {
+        let upvalue = UpvalueRef { index, is_local };
+        let upvalues = &mut self.functions[function].upvalues;
+        if let Some(existing) = upvalues.iter().position(|u| *u == upvalue) {
+            return existing as u16;
+        }
+        if upvalues.len() > u16::MAX as usize {
+            self.error("Too many closure variables in function.");
+            return 0;
+        }
+        upvalues.push(upvalue);
+        (upvalues.len() - 1) as u16
+    }
+
+    // token is only used to say where things went wrong if it's a global
+    // that turns out not to exist
+    fn load_variable(&mut self, name: &str, token: &Token) {
+        let (op, arg) = self.variable_op(name, token, false);
+        self.emit_op(op);
+        self.emit_u16(arg);
+    }
+
+    fn store_variable(&mut self, name: &str, token: &Token) {
+        let (op, arg) = self.variable_op(name, token, true);
+        self.emit_op(op);
+        self.emit_u16(arg);
+    }
+
+    fn variable_op(&mut self, name: &str, token: &Token, set: bool) -> (OpCode, u16) {
+        let function = self.functions.len() - 1;
+        if let Some(slot) = self.resolve_local(function, name) {
+            let op = if set {
+                OpCode::SetLocal
+            } else {
+                OpCode::GetLocal
+            };
+            return (op, slot);
+        }
+        if let Some(index) = self.resolve_upvalue(function, name) {
+            let op = if set {
+                OpCode::SetUpvalue
+            } else {
+                OpCode::GetUpvalue
+            };
+            return (op, index);
+        }
+
+        let constant = self.identifier_constant(name);
+        self.add_site(token, token.span(), token.span());
+        let op = if set {
+            OpCode::SetGlobal
+        } else {
+            OpCode::GetGlobal
+        };
+        (op, constant)
+    }
+
+    // Locals are already sitting in their slot on the stack.  Globals have
+    // to be taken off it and put in the table.
+    fn define_variable(&mut self, name: &str) {
+        if self.current().scope_depth > 0 {
+            self.add_local(name);
+        } else {
+            let constant = self.identifier_constant(name);
+            self.emit_op(OpCode::DefineGlobal);
+            self.emit_u16(constant);
+        }
+    }
+
+    /////////////////////////////////////////////////////////////////////////
+    // Emitting code
+    /////////////////////////////////////////////////////////////////////////
+    fn current(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().unwrap()
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        let line = self.line;
+        self.current().function.chunk.write(byte, line);
+    }
+
+    fn emit_op(&mut self, op: OpCode) {
+        let line = self.line;
+        self.current().function.chunk.write_op(op, line);
+    }
+
+    fn emit_u16(&mut self, value: u16) {
+        let line = self.line;
+        self.current().function.chunk.write_u16(value, line);
+    }
+
+    fn make_constant(&mut self, value: Value) -> u16 {
+        let index = self.current().function.chunk.add_constant(value);
+        match u16::try_from(index) {
+            Ok(index) => index,
+            Err(_) => {
+                self.error("Too many constants in one chunk.");
+                0
+            }
+        }
+    }
+
+    fn emit_constant(&mut self, value: Value) {
+        let constant = self.make_constant(value);
+        self.emit_op(OpCode::Constant);
+        self.emit_u16(constant);
+    }
+
+    fn identifier_constant(&mut self, name: &str) -> u16 {
+        let name = self.heap.alloc_string(name);
+        self.make_constant(name)
+    }
+
+    // Has to be called just before emitting the instruction that might fail
+    fn add_site(&mut self, token: &Token, span: Span, operand_span: Span) {
+        let chunk = &mut self.current().function.chunk;
+        let site = ErrorSite {
+            offset: chunk.code.len(),
+            token: token.clone(),
+            span,
+            operand_span,
+        };
+        chunk.add_site(site);
+    }
+
+    // Jumps are emitted with a dummy offset that gets patched once we know
+    // where they're going.  Hands back where the offset is to be patched.
+    fn emit_jump(&mut self, op: OpCode) -> usize {
+        self.emit_op(op);
+        self.emit_u16(u16::MAX);
+        self.current().function.chunk.code.len() - 2
+    }
+
+    fn patch_jump(&mut self, offset: usize) {
+        let jump = self.current().function.chunk.code.len() - offset - 2;
+        let jump = match u16::try_from(jump) {
+            Ok(jump) => jump,
+            Err(_) => {
+                self.error("Too much code to jump over.");
+                return;
+            }
+        };
+        let code = &mut self.current().function.chunk.code;
+        code[offset] = (jump >> 8) as u8;
+        code[offset + 1] = (jump & 0xff) as u8;
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        self.emit_op(OpCode::Loop);
+        let offset = self.current().function.chunk.code.len() - loop_start + 2;
+        match u16::try_from(offset) {
+            Ok(offset) => self.emit_u16(offset),
+            Err(_) => self.error("Loop body too large."),
+        }
+    }
+
+    fn error(&mut self, msg: &str) {
+        self.errors
+            .push(LoxError::new_text_only(Some(self.line), msg));
+    }
+}
+
+impl<'a> sstructs::Visitor for Compiler<'a> {
+    fn block(&mut self, stmt: &sstructs::block) -> Result<ParseReturn, LoxError> {
+        self.begin_scope();
+        for inner in &stmt.statements {
+            self.statement(&**inner);
+        }
+        self.end_scope();
+        Ok(ParseReturn::Unit)
+    }
+
+    // The class sits in a stack slot while its methods are attached.  For a
+    // local class that's the class's own variable.  For a global it's a
+    // hidden temporary and the class only goes into the globals once it's
+    // complete, like the tree walker does it.  With a superclass there's a
+    // scope around the methods holding "super" for them to capture.
+    fn class(&mut self, stmt: &sstructs::class) -> Result<ParseReturn, LoxError> {
+        self.line = stmt.name.line;
+        let name = self.identifier_constant(&stmt.name.lexeme);
+        let global = self.current().scope_depth == 0;
+        if global {
+            self.begin_scope();
+            self.add_local("");
+        } else {
+            self.add_local(&stmt.name.lexeme);
+        }
+        let class_slot = (self.current().locals.len() - 1) as u16;
+
+        self.emit_op(OpCode::Class);
+        self.emit_u16(name);
+
+        if let Some(superclass) = &stmt.superclass {
+            self.begin_scope();
+            self.load_variable(&superclass.name.lexeme, &superclass.name);
+            self.add_local("super");
+
+            self.emit_op(OpCode::GetLocal);
+            self.emit_u16(class_slot);
+            let name_span = superclass.name.span();
+            self.add_site(&superclass.name, name_span, name_span);
+            self.emit_op(OpCode::Inherit);
+        }
+
+        self.emit_op(OpCode::GetLocal);
+        self.emit_u16(class_slot);
+        for method in &stmt.methods {
+            let ftype = if method.name.lexeme == "init" {
+                FunctionType::Initializer
+            } else {
+                FunctionType::Method
+            };
+            self.function_body(method, ftype);
+            let method_name = self.identifier_constant(&method.name.lexeme);
+            self.emit_op(OpCode::Method);
+            self.emit_u16(method_name);
+        }
+        self.emit_op(OpCode::Pop);
+
+        if stmt.superclass.is_some() {
+            self.end_scope();
+        }
+        if global {
+            self.emit_op(OpCode::DefineGlobal);
+            self.emit_u16(name);
+            let state = self.current();
+            state.locals.pop();
+            state.scope_depth -= 1;
+        }
+        Ok(ParseReturn::Unit)
+    }
+
+    fn expression(&mut self, stmt: &sstructs::expression) -> Result<ParseReturn, LoxError> {
+        self.expression(&*stmt.expression);
+        self.emit_op(OpCode::Pop);
+        Ok(ParseReturn::Unit)
+    }
+
+    // A local function is defined before its body is compiled so it can
+    // call itself
+    fn function(&mut self, stmt: &sstructs::function) -> Result<ParseReturn, LoxError> {
+        if self.current().scope_depth > 0 {
+            self.add_local(&stmt.name.lexeme);
+            self.function_body(stmt, FunctionType::Function);
+        } else {
+            self.function_body(stmt, FunctionType::Function);
+            self.define_variable(&stmt.name.lexeme);
+        }
+        Ok(ParseReturn::Unit)
+    }
+
+    fn if_stmt(&mut self, stmt: &sstructs::if_stmt) -> Result<ParseReturn, LoxError> {
+        self.expression(&*stmt.condition);
+        let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_op(OpCode::Pop);
+        self.statement(&*stmt.then_branch);
+        let else_jump = self.emit_jump(OpCode::Jump);
+
+        self.patch_jump(then_jump);
+        self.emit_op(OpCode::Pop);
+        if let Some(else_branch) = &stmt.else_branch {
+            self.statement(&**else_branch);
+        }
+        self.patch_jump(else_jump);
+        Ok(ParseReturn::Unit)
+    }
+
+    fn print(&mut self, stmt: &sstructs::print) -> Result<ParseReturn, LoxError> {
+        self.expression(&*stmt.expression);
+        self.emit_op(OpCode::Print);
+        Ok(ParseReturn::Unit)
+    }
+
+    fn return_stmt(&mut self, stmt: &sstructs::return_stmt) -> Result<ParseReturn, LoxError> {
+        self.line = stmt.keyword.line;
+        match &stmt.value {
+            Some(value) => {
+                self.expression(&**value);
+                self.emit_op(OpCode::Return);
+            }
+            None => self.emit_return(),
+        }
+        Ok(ParseReturn::Unit)
+    }
+
+    fn var(&mut self, stmt: &sstructs::var) -> Result<ParseReturn, LoxError> {
+        self.line = stmt.name.line;
+        match &stmt.initializer {
+            Some(initializer) => self.expression(&**initializer),
+            None => self.emit_op(OpCode::Nil),
+        }
+        self.define_variable(&stmt.name.lexeme);
+        Ok(ParseReturn::Unit)
+    }
+
+    fn while_stmt(&mut self, stmt: &sstructs::while_stmt) -> Result<ParseReturn, LoxError> {
+        let loop_start = self.current().function.chunk.code.len();
+        self.expression(&*stmt.condition);
+        let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_op(OpCode::Pop);
+        self.statement(&*stmt.body);
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exit_jump);
+        self.emit_op(OpCode::Pop);
+        Ok(ParseReturn::Unit)
+    }
+}
+
+impl<'a> Visitor for Compiler<'a> {
+    fn assign(&mut self, expr: &assign) -> Result<ParseReturn, LoxError> {
+        self.expression(&*expr.value);
+        self.line = expr.name.line;
+        self.store_variable(&expr.name.lexeme, &expr.name);
+        Ok(ParseReturn::Unit)
+    }
+
+    fn binary(&mut self, expr: &binary) -> Result<ParseReturn, LoxError> {
+        self.expression(&*expr.left);
+        self.expression(&*expr.right);
+        self.line = expr.operator.line;
+
+        let op = match expr.operator.ttype {
+            TokenType::EqualEqual | TokenType::BangEqual => OpCode::Equal,
+            TokenType::Greater => OpCode::Greater,
+            TokenType::GreaterEqual => OpCode::GreaterEqual,
+            TokenType::Less => OpCode::Less,
+            TokenType::LessEqual => OpCode::LessEqual,
+            TokenType::Plus => OpCode::Add,
+            TokenType::Minus => OpCode::Subtract,
+            TokenType::Star => OpCode::Multiply,
+            TokenType::Slash => OpCode::Divide,
+            TokenType::TildeSlash => OpCode::IntDivide,
+            TokenType::Percent => OpCode::Modulo,
+            TokenType::StarStar => OpCode::Power,
+            TokenType::Ampersand => OpCode::BitAnd,
+            TokenType::Pipe => OpCode::BitOr,
+            TokenType::Caret => OpCode::BitXor,
+            TokenType::LessLess => OpCode::ShiftLeft,
+            TokenType::GreaterGreater => OpCode::ShiftRight,
+            _ => panic!("Unhandled operator in compiler"),
+        };
+        self.add_site(&expr.operator, expr.span, expr.span);
+        self.emit_op(op);
+        if expr.operator.ttype == TokenType::BangEqual {
+            self.emit_op(OpCode::Not);
+        }
+        Ok(ParseReturn::Unit)
+    }
+
+    fn call(&mut self, expr: &call) -> Result<ParseReturn, LoxError> {
+        self.expression(&*expr.callee);
+        for argument in &expr.arguments {
+            self.expression(&**argument);
+        }
+        self.line = expr.paren.line;
+        self.add_site(&expr.paren, expr.span, expr.callee.span());
+        self.emit_op(OpCode::Call);
+        self.emit_byte(expr.arguments.len() as u8);
+        Ok(ParseReturn::Unit)
+    }
+
+    fn get(&mut self, expr: &get) -> Result<ParseReturn, LoxError> {
+        self.expression(&*expr.object);
+        self.line = expr.name.line;
+        let name = self.identifier_constant(&expr.name.lexeme);
+        self.add_site(&expr.name, expr.name.span(), expr.object.span());
+        self.emit_op(OpCode::GetProperty);
+        self.emit_u16(name);
+        Ok(ParseReturn::Unit)
+    }
+
+    fn grouping(&mut self, expr: &grouping) -> Result<ParseReturn, LoxError> {
+        self.expression(&*expr.expression);
+        Ok(ParseReturn::Unit)
+    }
+
+    fn interpolation(&mut self, expr: &interpolation) -> Result<ParseReturn, LoxError> {
+        for part in &expr.parts {
+            self.expression(&**part);
+        }
+        match u16::try_from(expr.parts.len()) {
+            Ok(count) => {
+                self.emit_op(OpCode::Interpolate);
+                self.emit_u16(count);
+            }
+            Err(_) => self.error("Too many parts in string interpolation."),
+        }
+        Ok(ParseReturn::Unit)
+    }
+
+    fn literal(&mut self, expr: &literal) -> Result<ParseReturn, LoxError> {
+        match &expr.value {
+            TokenType::Number(_) => self.emit_constant(expr.value.num_value().into()),
+            TokenType::String(s) => {
+                let string = self.heap.alloc_string(s);
+                self.emit_constant(string);
+            }
+            TokenType::True => self.emit_op(OpCode::True),
+            TokenType::False => self.emit_op(OpCode::False),
+            _ => self.emit_op(OpCode::Nil),
+        }
+        Ok(ParseReturn::Unit)
+    }
+
+    fn logical(&mut self, expr: &logical) -> Result<ParseReturn, LoxError> {
+        self.expression(&*expr.left);
+        if expr.operator.ttype == TokenType::Or {
+            let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+            let end_jump = self.emit_jump(OpCode::Jump);
+            self.patch_jump(else_jump);
+            self.emit_op(OpCode::Pop);
+            self.expression(&*expr.right);
+            self.patch_jump(end_jump);
+        } else {
+            let end_jump = self.emit_jump(OpCode::JumpIfFalse);
+            self.emit_op(OpCode::Pop);
+            self.expression(&*expr.right);
+            self.patch_jump(end_jump);
+        }
+        Ok(ParseReturn::Unit)
+    }
+
+    // The tree walker complains about a non-instance before it evaluates
+    // the value so we check in between as well
+    fn set(&mut self, expr: &set) -> Result<ParseReturn, LoxError> {
+        self.expression(&*expr.object);
+        self.line = expr.name.line;
+        self.add_site(&expr.name, expr.name.span(), expr.object.span());
+        self.emit_op(OpCode::CheckInstance);
+        self.expression(&*expr.value);
+        let name = self.identifier_constant(&expr.name.lexeme);
+        self.emit_op(OpCode::SetProperty);
+        self.emit_u16(name);
+        Ok(ParseReturn::Unit)
+    }
+
+    fn super_expr(&mut self, expr: &super_expr) -> Result<ParseReturn, LoxError> {
+        self.line = expr.keyword.line;
+        self.load_variable("this", &expr.keyword);
+        self.load_variable("super", &expr.keyword);
+        let name = self.identifier_constant(&expr.method.lexeme);
+        let method_span = expr.method.span();
+        self.add_site(&expr.method, method_span, method_span);
+        self.emit_op(OpCode::GetSuper);
+        self.emit_u16(name);
+        Ok(ParseReturn::Unit)
+    }
+
+    fn this(&mut self, expr: &this) -> Result<ParseReturn, LoxError> {
+        self.line = expr.keyword.line;
+        self.load_variable("this", &expr.keyword);
+        Ok(ParseReturn::Unit)
+    }
+
+    fn unary(&mut self, expr: &unary) -> Result<ParseReturn, LoxError> {
+        self.expression(&*expr.right);
+        self.line = expr.operator.line;
+        let op = match expr.operator.ttype {
+            TokenType::Minus => OpCode::Negate,
+            TokenType::Bang => OpCode::Not,
+            TokenType::Tilde => OpCode::BitNot,
+            _ => panic!("Unary with invalid operation in compiler"),
+        };
+        self.add_site(&expr.operator, expr.span, expr.span);
+        self.emit_op(op);
+        Ok(ParseReturn::Unit)
+    }
+
+    fn variable(&mut self, expr: &variable) -> Result<ParseReturn, LoxError> {
+        self.line = expr.name.line;
+        self.load_variable(&expr.name.lexeme, &expr.name);
+        Ok(ParseReturn::Unit)
+    }
+}
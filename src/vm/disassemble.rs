@@ -0,0 +1,215 @@
+use crate::vm;
+
+use vm::chunk::{Chunk, OpCode};
+use vm::value::{Function, Heap, Obj, Value};
+
+// Lists a function's code followed by that of every function defined in
+// it, clox style:
+//
+//   offset  line  opcode  operand  what the operand means
+//
+// A "|" in the line column means same line as the instruction before.
+pub fn disassemble_function(heap: &Heap, function: &Function) -> String {
+    let mut text = format!("== {} ==\n", function_name(function));
+    let mut offset = 0;
+    while offset < function.chunk.code.len() {
+        let (instruction, next) = disassemble_instruction(heap, &function.chunk, offset);
+        text += &instruction;
+        text += "\n";
+        offset = next;
+    }
+
+    for constant in &function.chunk.constants {
+        if let Value::Obj(obj) = constant {
+            if let Obj::Function(inner) = heap.get(*obj) {
+                text += "\n";
+                text += &disassemble_function(heap, inner);
+            }
+        }
+    }
+    text
+}
+
+// Hands back the instruction at offset as text and where the next one starts
+pub fn disassemble_instruction(heap: &Heap, chunk: &Chunk, offset: usize) -> (String, usize) {
+    let line = if offset > 0 && chunk.lines[offset] == chunk.lines[offset - 1] {
+        "   |".to_string()
+    } else {
+        format!("{:4}", chunk.lines[offset])
+    };
+    let prefix = format!("{:04} {} ", offset, line);
+
+    let op = match OpCode::from_byte(chunk.code[offset]) {
+        Some(op) => op,
+        None => {
+            let text = format!("{}Unknown opcode {}", prefix, chunk.code[offset]);
+            return (text, offset + 1);
+        }
+    };
+    let name = format!("{:?}", op);
+
+    let (text, next) = match op {
+        OpCode::Constant
+        | OpCode::GetGlobal
+        | OpCode::DefineGlobal
+        | OpCode::SetGlobal
+        | OpCode::GetProperty
+        | OpCode::SetProperty
+        | OpCode::GetSuper
+        | OpCode::Class
+        | OpCode::Method => {
+            let index = chunk.read_u16(offset + 1);
+            let constant = describe(heap, chunk.constants[index as usize]);
+            (format!("{:<16} {:4} {}", name, index, constant), offset + 3)
+        }
+
+        OpCode::GetLocal
+        | OpCode::SetLocal
+        | OpCode::GetUpvalue
+        | OpCode::SetUpvalue
+        | OpCode::Interpolate => {
+            let operand = chunk.read_u16(offset + 1);
+            (format!("{:<16} {:4}", name, operand), offset + 3)
+        }
+
+        OpCode::Call => {
+            let argc = chunk.code[offset + 1];
+            (format!("{:<16} {:4}", name, argc), offset + 2)
+        }
+
+        // Jumps show where they go rather than how far
+        OpCode::Jump | OpCode::JumpIfFalse => {
+            let target = offset + 3 + chunk.read_u16(offset + 1) as usize;
+            (
+                format!("{:<16} {:4} -> {}", name, offset, target),
+                offset + 3,
+            )
+        }
+        OpCode::Loop => {
+            let target = offset + 3 - chunk.read_u16(offset + 1) as usize;
+            (
+                format!("{:<16} {:4} -> {}", name, offset, target),
+                offset + 3,
+            )
+        }
+
+        // Each captured variable gets a line of its own underneath
+        OpCode::Closure => {
+            let index = chunk.read_u16(offset + 1);
+            let constant = chunk.constants[index as usize];
+            let mut text = format!("{:<16} {:4} {}", name, index, describe(heap, constant));
+            let mut next = offset + 3;
+            let upvalue_count = match constant {
+                Value::Obj(obj) => match heap.get(obj) {
+                    Obj::Function(function) => function.upvalue_count,
+                    _ => 0,
+                },
+                _ => 0,
+            };
+            for _ in 0..upvalue_count {
+                let kind = if chunk.code[next] == 1 {
+                    "local"
+                } else {
+                    "upvalue"
+                };
+                let index = chunk.read_u16(next + 1);
+                text += &format!("\n{:04}    |   {:<16} {:4}", next, kind, index);
+                next += 3;
+            }
+            (text, next)
+        }
+
+        _ => (name, offset + 1),
+    };
+    (prefix + &text, next)
+}
+
+// One line showing everything on the stack, bottom first
+pub fn stack_line(heap: &Heap, stack: &[Value]) -> String {
+    let mut text = "          ".to_string();
+    for value in stack {
+        text += &format!("[ {} ]", describe(heap, *value));
+    }
+    text
+}
+
+// Like printing the value except strings are quoted so they stand out
+fn describe(heap: &Heap, value: Value) -> String {
+    if let Value::Obj(obj) = value {
+        match heap.get(obj) {
+            Obj::String(s) => return format!("\"{}\"", s),
+            Obj::Function(function) => return function_label(function),
+            Obj::Closure(closure) => return function_label(&closure.function),
+            _ => (),
+        }
+    }
+    heap.to_string(value)
+}
+
+fn function_label(function: &Function) -> String {
+    if function.name.is_empty() {
+        "<script>".to_string()
+    } else {
+        format!("<fn {}>", function.name)
+    }
+}
+
+fn function_name(function: &Function) -> &str {
+    if function.name.is_empty() {
+        "<script>"
+    } else {
+        &function.name
+    }
+}
+
+#[cfg(test)]
+use crate::setup::compile::front_end;
+#[cfg(test)]
+use crate::vm::compiler::Compiler;
+
+#[test]
+pub fn disassemble_test() {
+    let statements = front_end(
+        "fun adder(n) {
+            return fun_body(n);
+        }
+        var a = \"x\";
+        while (a) a = nil;",
+    );
+    let statements = match statements {
+        Ok(statements) => statements,
+        Err(_) => panic!("Program didn't get through the front end"),
+    };
+    let mut heap = Heap::new();
+    let function = match Compiler::new(&mut heap).compile(&statements) {
+        Ok(function) => function,
+        Err(_) => panic!("Program didn't compile"),
+    };
+
+    let expected = "\
+== <script> ==
+0000    1 Closure             0 <fn adder>
+0003    | DefineGlobal        1 \"adder\"
+0006    4 Constant            2 \"x\"
+0009    | DefineGlobal        3 \"a\"
+0012    5 GetGlobal           4 \"a\"
+0015    | JumpIfFalse        15 -> 27
+0018    | Pop
+0019    | Nil
+0020    | SetGlobal           5 \"a\"
+0023    | Pop
+0024    | Loop               24 -> 12
+0027    | Pop
+0028    | Nil
+0029    | Return
+
+== adder ==
+0000    2 GetGlobal           0 \"fun_body\"
+0003    | GetLocal            1
+0006    | Call                1
+0008    | Return
+0009    | Nil
+0010    | Return
+";
+    assert_eq!(expected, disassemble_function(&heap, &function));
+}
@@ -0,0 +1,1055 @@
+use crate::lox_error;
+use crate::parser;
+use crate::scanner;
+use crate::vm;
+
+use lox_error::lox_error::{LoxError, LoxErrorList};
+use parser::evaluate::{
+    checked, compare_numbers, floor_div, floor_mod, integer_power, shift_amount, FRAMES_MAX,
+};
+use parser::parser::Stmt;
+use scanner::symbol::Symbol;
+use scanner::token_type::NumberValue;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io::{stderr, stdout, Write};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use vm::bytecode_file::read_program;
+use vm::chunk::OpCode;
+use vm::compiler::Compiler;
+use vm::disassemble::{disassemble_function, disassemble_instruction, stack_line};
+use vm::value::{
+    BoundMethod, Class, Closure, Function, Heap, HeapStats, Instance, Native, Obj, ObjRef, Upvalue,
+    Value,
+};
+
+struct CallFrame {
+    closure: ObjRef,
+    function: Rc<Function>,
+    ip: usize,
+    // Where the frame's slot 0 is on the stack
+    base: usize,
+    // Where the instruction being run started so errors can find its site
+    op_start: usize,
+}
+
+// The bytecode backend.  Programs come in as resolved statements just like
+// they do for the Evaluator and are compiled before they're run.  Globals
+// and the heap last as long as the VM does so the REPL works the same way.
+pub struct Vm {
+    heap: Heap,
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    globals: HashMap<Symbol, Value>,
+    // Upvalues still pointing into the stack, ordered by slot
+    open_upvalues: Vec<ObjRef>,
+    output: Box<dyn Write>,
+    // Debugging aids.  Their output goes to stderr by default so it doesn't
+    // get mixed up with what the program prints.
+    disassemble: bool,
+    trace: bool,
+    debug_output: Box<dyn Write>,
+    // Collect on every allocation to shake out anything not being rooted
+    stress_gc: bool,
+    // Saves interning "init" every time a class is called
+    init_symbol: Symbol,
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Self::with_output(Box::new(stdout()))
+    }
+
+    pub fn with_output(output: Box<dyn Write>) -> Self {
+        let mut vm = Vm {
+            heap: Heap::new(),
+            stack: vec![],
+            frames: vec![],
+            globals: HashMap::new(),
+            open_upvalues: vec![],
+            output,
+            disassemble: false,
+            trace: false,
+            debug_output: Box::new(stderr()),
+            stress_gc: false,
+            init_symbol: Symbol::intern("init"),
+        };
+
+        vm.define_native("clock", 0, |_| {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(|_| LoxError::new_text_only(None, "System clock is before 1970"))?;
+            Ok(Value::Number(now.as_secs_f64()))
+        });
+        vm
+    }
+
+    // Same as Evaluator::define_native.  The VM checks the arity.
+    pub fn define_native<F>(&mut self, name: &str, arity: usize, function: F)
+    where
+        F: Fn(&[Value]) -> Result<Value, LoxError> + 'static,
+    {
+        let native = Native {
+            name: name.to_string(),
+            arity,
+            function: Box::new(function),
+        };
+        let native = self.heap.alloc(Obj::Native(Rc::new(native)));
+        self.globals
+            .insert(Symbol::intern(name), Value::Obj(native));
+    }
+
+    // Lists the bytecode of everything compiled before it's run
+    pub fn set_disassemble(&mut self, disassemble: bool) {
+        self.disassemble = disassemble;
+    }
+
+    // Shows the stack and the instruction about to run at every step
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    pub fn set_stress_gc(&mut self, stress_gc: bool) {
+        self.stress_gc = stress_gc;
+    }
+
+    pub fn heap_stats(&self) -> HeapStats {
+        self.heap.stats()
+    }
+
+    // Only tests want the trace anywhere but stderr
+    #[cfg(test)]
+    pub fn set_debug_output(&mut self, debug_output: Box<dyn Write>) {
+        self.debug_output = debug_output;
+    }
+
+    pub fn interpret(&mut self, statements: &[Stmt]) -> LoxErrorList {
+        match Compiler::new(&mut self.heap).compile(statements) {
+            Ok(function) => self.execute(function),
+            Err(errors) => errors,
+        }
+    }
+
+    // Runs the contents of a .loxc file
+    pub fn run_compiled(&mut self, bytes: &[u8]) -> LoxErrorList {
+        match read_program(&mut self.heap, bytes) {
+            Ok(function) => self.execute(function),
+            Err(error) => LoxErrorList::single(error),
+        }
+    }
+
+    fn execute(&mut self, function: Rc<Function>) -> LoxErrorList {
+        if self.disassemble {
+            let listing = disassemble_function(&self.heap, &function);
+            if let Err(error) = write!(self.debug_output, "{}", listing) {
+                return LoxErrorList::single(output_error(error));
+            }
+        }
+        self.run_function(function)
+    }
+
+    // A runtime error ends the script and leaves the VM ready for the next
+    fn run_function(&mut self, function: Rc<Function>) -> LoxErrorList {
+        // Nothing roots the script's constants until its closure is on the
+        // stack so this mustn't set off a collection
+        let closure = self.heap.alloc(Obj::Closure(Closure {
+            function: function.clone(),
+            upvalues: vec![],
+        }));
+        self.push(Value::Obj(closure));
+
+        let result = self
+            .call_closure(closure, function, 0)
+            .and_then(|_| self.run());
+        match result {
+            Ok(()) => LoxErrorList::new(),
+            Err(error) => {
+                self.stack.clear();
+                self.frames.clear();
+                self.open_upvalues.clear();
+                LoxErrorList::single(error)
+            }
+        }
+    }
+
+    fn run(&mut self) -> Result<(), LoxError> {
+        loop {
+            if self.trace {
+                self.trace_instruction()?;
+            }
+            let frame = self.frames.last_mut().unwrap();
+            frame.op_start = frame.ip;
+            // Neither this nor read_name() can fail on code from the
+            // compiler or code that got through loading
+            let op = match OpCode::from_byte(self.read_byte()) {
+                Some(op) => op,
+                None => return Err(self.error("Invalid opcode.", false)),
+            };
+
+            match op {
+                OpCode::Constant => {
+                    let constant = self.read_constant();
+                    self.push(constant);
+                }
+                OpCode::Nil => self.push(Value::Nil),
+                OpCode::True => self.push(Value::Bool(true)),
+                OpCode::False => self.push(Value::Bool(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+
+                OpCode::GetLocal => {
+                    let slot = self.frame().base + self.read_u16() as usize;
+                    self.push(self.stack[slot]);
+                }
+                OpCode::SetLocal => {
+                    let slot = self.frame().base + self.read_u16() as usize;
+                    self.stack[slot] = self.peek(0);
+                }
+                OpCode::GetGlobal => {
+                    let name = self.read_name()?;
+                    match self.globals.get(&name) {
+                        Some(value) => self.push(*value),
+                        None => {
+                            let msg = format!("Undefined variable '{}'.", name);
+                            return Err(self.error(&msg, false));
+                        }
+                    }
+                }
+                OpCode::DefineGlobal => {
+                    let name = self.read_name()?;
+                    let value = self.pop();
+                    self.globals.insert(name, value);
+                }
+                OpCode::SetGlobal => {
+                    let name = self.read_name()?;
+                    if !self.globals.contains_key(&name) {
+                        let msg = format!("Undefined variable '{}'.", name);
+                        return Err(self.error(&msg, false));
+                    }
+                    self.globals.insert(name, self.peek(0));
+                }
+                OpCode::GetUpvalue => {
+                    let index = self.read_u16();
+                    let upvalue = self.upvalue(index);
+                    let value = match self.heap.get(upvalue) {
+                        Obj::Upvalue(Upvalue::Open(slot)) => self.stack[*slot],
+                        Obj::Upvalue(Upvalue::Closed(value)) => *value,
+                        _ => unreachable!("Closure holding a non-upvalue"),
+                    };
+                    self.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let index = self.read_u16();
+                    let upvalue = self.upvalue(index);
+                    let value = self.peek(0);
+                    match self.heap.get_mut(upvalue) {
+                        Obj::Upvalue(Upvalue::Open(slot)) => {
+                            let slot = *slot;
+                            self.stack[slot] = value;
+                        }
+                        Obj::Upvalue(closed) => *closed = Upvalue::Closed(value),
+                        _ => unreachable!("Closure holding a non-upvalue"),
+                    }
+                }
+
+                // Fields shadow methods
+                OpCode::GetProperty => {
+                    let name = self.read_name()?;
+                    let (field, class) = match self.as_obj(self.peek(0)) {
+                        Some(Obj::Instance(instance)) => {
+                            (instance.fields.get(&name).copied(), instance.class)
+                        }
+                        _ => return Err(self.error("Only instances have properties.", true)),
+                    };
+                    match field {
+                        Some(value) => {
+                            self.pop();
+                            self.push(value);
+                        }
+                        None => self.bind_method(class, &name)?,
+                    }
+                }
+                OpCode::SetProperty => {
+                    let name = self.read_name()?;
+                    let value = self.pop();
+                    let object = self.pop();
+                    if let Value::Obj(object) = object {
+                        if let Obj::Instance(instance) = self.heap.get_mut(object) {
+                            instance.fields.insert(name, value);
+                        }
+                    }
+                    self.push(value);
+                }
+                OpCode::CheckInstance => {
+                    if !matches!(self.as_obj(self.peek(0)), Some(Obj::Instance(_))) {
+                        return Err(self.error("Only instances have fields.", true));
+                    }
+                }
+                OpCode::GetSuper => {
+                    let name = self.read_name()?;
+                    let superclass = match self.pop() {
+                        Value::Obj(superclass) => superclass,
+                        _ => return Err(self.error("Superclass must be a class.", false)),
+                    };
+                    self.bind_method(superclass, &name)?;
+                }
+
+                OpCode::Equal => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.push(Value::Bool(self.heap.values_equal(left, right)));
+                }
+                OpCode::Greater => self.comparison(Ordering::is_gt)?,
+                OpCode::GreaterEqual => self.comparison(Ordering::is_ge)?,
+                OpCode::Less => self.comparison(Ordering::is_lt)?,
+                OpCode::LessEqual => self.comparison(Ordering::is_le)?,
+
+                OpCode::Add => {
+                    let right = self.peek(0);
+                    let left = self.peek(1);
+                    if left.as_number().is_some() && right.as_number().is_some() {
+                        self.arithmetic(|a, b| checked(a.checked_add(b)), |a, b| a + b)?;
+                    } else if let (Some(a), Some(b)) =
+                        (self.heap.as_str(left), self.heap.as_str(right))
+                    {
+                        let concat = format!("{}{}", a, b);
+                        self.pop();
+                        self.pop();
+                        let value = self.alloc_string(&concat);
+                        self.push(value);
+                    } else {
+                        return Err(self.error("Mismatched types", false));
+                    }
+                }
+                OpCode::Subtract => {
+                    self.arithmetic(|a, b| checked(a.checked_sub(b)), |a, b| a - b)?
+                }
+                OpCode::Multiply => {
+                    self.arithmetic(|a, b| checked(a.checked_mul(b)), |a, b| a * b)?
+                }
+                OpCode::Divide => {
+                    let (a, b) = self.numbers()?;
+                    self.push(Value::Number(a.as_f64() / b.as_f64()));
+                }
+                OpCode::IntDivide => self.arithmetic(
+                    |a, b| match b {
+                        0 => Err("Division by zero."),
+                        _ => checked(floor_div(a, b)),
+                    },
+                    |a, b| (a / b).floor(),
+                )?,
+                OpCode::Modulo => self.arithmetic(
+                    |a, b| match b {
+                        0 => Err("Division by zero."),
+                        _ => Ok(floor_mod(a, b)),
+                    },
+                    |a, b| a - b * (a / b).floor(),
+                )?,
+                OpCode::Power => match self.numbers()? {
+                    (NumberValue::Integer(a), NumberValue::Integer(b)) if b >= 0 => {
+                        let result =
+                            checked(integer_power(a, b)).map_err(|msg| self.error(msg, false))?;
+                        self.push(Value::Integer(result));
+                    }
+                    (a, b) => self.push(Value::Number(a.as_f64().powf(b.as_f64()))),
+                },
+                OpCode::BitAnd => self.bitwise(|a, b| Ok(a & b))?,
+                OpCode::BitOr => self.bitwise(|a, b| Ok(a | b))?,
+                OpCode::BitXor => self.bitwise(|a, b| Ok(a ^ b))?,
+                OpCode::ShiftLeft => self.bitwise(|a, b| shift_amount(b).map(|b| a << b))?,
+                OpCode::ShiftRight => self.bitwise(|a, b| shift_amount(b).map(|b| a >> b))?,
+
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.push(Value::Bool(value.is_falsey()));
+                }
+                OpCode::Negate => {
+                    let value = self.pop();
+                    match value.as_number() {
+                        Some(NumberValue::Integer(i)) => match i.checked_neg() {
+                            Some(i) => self.push(Value::Integer(i)),
+                            None => return Err(self.error("Integer overflow.", false)),
+                        },
+                        Some(NumberValue::Float(f)) => self.push(Value::Number(-f)),
+                        None => {
+                            let msg =
+                                format!("Expected number but found {}", self.heap.type_name(value));
+                            return Err(self.error(&msg, false));
+                        }
+                    }
+                }
+                OpCode::BitNot => {
+                    let value = self.pop();
+                    let value = self.integer(value)?;
+                    self.push(Value::Integer(!value));
+                }
+
+                OpCode::Interpolate => {
+                    let count = self.read_u16() as usize;
+                    let parts = self.stack.split_off(self.stack.len() - count);
+                    let mut result = String::new();
+                    for part in parts {
+                        result += &self.heap.to_string(part);
+                    }
+                    let value = self.alloc_string(&result);
+                    self.push(value);
+                }
+                OpCode::Print => {
+                    let value = self.pop();
+                    let text = self.heap.to_string(value);
+                    writeln!(self.output, "{}", text).map_err(output_error)?;
+                }
+
+                OpCode::Jump => {
+                    let offset = self.read_u16() as usize;
+                    self.frames.last_mut().unwrap().ip += offset;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = self.read_u16() as usize;
+                    if self.peek(0).is_falsey() {
+                        self.frames.last_mut().unwrap().ip += offset;
+                    }
+                }
+                OpCode::Loop => {
+                    let offset = self.read_u16() as usize;
+                    self.frames.last_mut().unwrap().ip -= offset;
+                }
+
+                OpCode::Call => {
+                    let argc = self.read_byte() as usize;
+                    self.call_value(self.peek(argc), argc)?;
+                }
+                OpCode::Closure => {
+                    let constant = self.read_constant();
+                    let function = match self.as_obj(constant) {
+                        Some(Obj::Function(function)) => function.clone(),
+                        _ => unreachable!("Closure of a non-function"),
+                    };
+                    let mut upvalues = Vec::with_capacity(function.upvalue_count);
+                    for _ in 0..function.upvalue_count {
+                        let is_local = self.read_byte() == 1;
+                        let index = self.read_u16();
+                        if is_local {
+                            let slot = self.frame().base + index as usize;
+                            upvalues.push(self.capture_upvalue(slot));
+                        } else {
+                            upvalues.push(self.upvalue(index));
+                        }
+                    }
+                    let closure = self.alloc(Obj::Closure(Closure { function, upvalues }));
+                    self.push(Value::Obj(closure));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().unwrap();
+                    self.close_upvalues(frame.base);
+                    self.stack.truncate(frame.base);
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    self.push(result);
+                }
+
+                OpCode::Class => {
+                    let name = self.read_name()?;
+                    let class = self.alloc(Obj::Class(Class {
+                        name,
+                        methods: HashMap::new(),
+                    }));
+                    self.push(Value::Obj(class));
+                }
+                OpCode::Inherit => {
+                    let methods = match self.as_obj(self.peek(1)) {
+                        Some(Obj::Class(superclass)) => superclass.methods.clone(),
+                        _ => return Err(self.error("Superclass must be a class.", false)),
+                    };
+                    if let Value::Obj(subclass) = self.pop() {
+                        if let Obj::Class(subclass) = self.heap.get_mut(subclass) {
+                            subclass.methods = methods;
+                        }
+                    }
+                }
+                OpCode::Method => {
+                    let name = self.read_name()?;
+                    let method = self.pop();
+                    // Only closures can be called as methods
+                    if let (Value::Obj(class), Value::Obj(method)) = (self.peek(0), method) {
+                        if !matches!(self.heap.get(method), Obj::Closure(_)) {
+                            return Err(self.error("Methods must be functions.", false));
+                        }
+                        if let Obj::Class(class) = self.heap.get_mut(class) {
+                            class.methods.insert(name, method);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn trace_instruction(&mut self) -> Result<(), LoxError> {
+        let frame = self.frame();
+        let (instruction, _) = disassemble_instruction(&self.heap, &frame.function.chunk, frame.ip);
+        let stack = stack_line(&self.heap, &self.stack);
+        writeln!(self.debug_output, "{}\n{}", stack, instruction).map_err(output_error)
+    }
+
+    /////////////////////////////////////////////////////////////////////////
+    // Calls
+    /////////////////////////////////////////////////////////////////////////
+    fn call_value(&mut self, callee: Value, argc: usize) -> Result<(), LoxError> {
+        let callee_slot = self.stack.len() - argc - 1;
+        match self.as_obj(callee) {
+            Some(Obj::Closure(closure)) => {
+                let function = closure.function.clone();
+                if let Value::Obj(callee) = callee {
+                    return self.call_closure(callee, function, argc);
+                }
+            }
+            Some(Obj::Native(native)) => {
+                let native = native.clone();
+                self.check_arity(native.arity, argc)?;
+                let result = (native.function)(&self.stack[callee_slot + 1..])?;
+                self.stack.truncate(callee_slot);
+                self.push(result);
+                return Ok(());
+            }
+            // The new instance takes the class's place on the stack so it's
+            // in slot 0 - "this" - for init
+            Some(Obj::Class(class)) => {
+                let init = class.methods.get(&self.init_symbol).copied();
+                let class = match callee {
+                    Value::Obj(class) => class,
+                    _ => unreachable!(),
+                };
+                let instance = self.alloc(Obj::Instance(Instance {
+                    class,
+                    fields: HashMap::new(),
+                }));
+                self.stack[callee_slot] = Value::Obj(instance);
+                return match init {
+                    Some(init) => {
+                        let function = self.closure_function(init);
+                        self.call_closure(init, function, argc)
+                    }
+                    None => self.check_arity(0, argc),
+                };
+            }
+            Some(Obj::BoundMethod(bound)) => {
+                let (receiver, method) = (bound.receiver, bound.method);
+                self.stack[callee_slot] = receiver;
+                let function = self.closure_function(method);
+                return self.call_closure(method, function, argc);
+            }
+            _ => (),
+        }
+        Err(self.error("Can only call functions and classes.", true))
+    }
+
+    fn call_closure(
+        &mut self,
+        closure: ObjRef,
+        function: Rc<Function>,
+        argc: usize,
+    ) -> Result<(), LoxError> {
+        self.check_arity(function.arity, argc)?;
+        if self.frames.len() == FRAMES_MAX {
+            return Err(self.error("Stack overflow.", false));
+        }
+        self.frames.push(CallFrame {
+            closure,
+            function,
+            ip: 0,
+            base: self.stack.len() - argc - 1,
+            op_start: 0,
+        });
+        Ok(())
+    }
+
+    fn check_arity(&self, arity: usize, argc: usize) -> Result<(), LoxError> {
+        if arity == argc {
+            Ok(())
+        } else {
+            let msg = format!("Expected {} arguments but got {}.", arity, argc);
+            Err(self.error(&msg, false))
+        }
+    }
+
+    fn closure_function(&self, closure: ObjRef) -> Rc<Function> {
+        match self.heap.get(closure) {
+            Obj::Closure(closure) => closure.function.clone(),
+            _ => unreachable!("Method isn't a closure"),
+        }
+    }
+
+    // Replaces the instance on top of the stack with one of its class's
+    // methods bound to it
+    fn bind_method(&mut self, class: ObjRef, name: &Symbol) -> Result<(), LoxError> {
+        let method = match self.heap.get(class) {
+            Obj::Class(class) => class.methods.get(name).copied(),
+            _ => None,
+        };
+        match method {
+            Some(method) => {
+                // The receiver stays on the stack until the bound method
+                // holds it in case allocating sets off a collection
+                let receiver = self.peek(0);
+                let bound = self.alloc(Obj::BoundMethod(BoundMethod { receiver, method }));
+                self.pop();
+                self.push(Value::Obj(bound));
+                Ok(())
+            }
+            None => {
+                let msg = format!("Undefined property '{}'.", name);
+                Err(self.error(&msg, false))
+            }
+        }
+    }
+
+    /////////////////////////////////////////////////////////////////////////
+    // Upvalues
+    /////////////////////////////////////////////////////////////////////////
+    fn upvalue(&self, index: u16) -> ObjRef {
+        match self.heap.get(self.frame().closure) {
+            Obj::Closure(closure) => closure.upvalues[index as usize],
+            _ => unreachable!("Frame without a closure"),
+        }
+    }
+
+    // Closures capturing the same variable have to share the upvalue
+    fn capture_upvalue(&mut self, slot: usize) -> ObjRef {
+        let mut insert_at = self.open_upvalues.len();
+        for (i, upvalue) in self.open_upvalues.iter().enumerate().rev() {
+            match self.open_slot(*upvalue) {
+                open if open == slot => return *upvalue,
+                open if open < slot => break,
+                _ => insert_at = i,
+            }
+        }
+        let upvalue = self.alloc(Obj::Upvalue(Upvalue::Open(slot)));
+        self.open_upvalues.insert(insert_at, upvalue);
+        upvalue
+    }
+
+    // Moves every variable at or above last off the stack and into its
+    // upvalue
+    fn close_upvalues(&mut self, last: usize) {
+        while let Some(upvalue) = self.open_upvalues.last().copied() {
+            let slot = self.open_slot(upvalue);
+            if slot < last {
+                break;
+            }
+            *self.heap.get_mut(upvalue) = Obj::Upvalue(Upvalue::Closed(self.stack[slot]));
+            self.open_upvalues.pop();
+        }
+    }
+
+    fn open_slot(&self, upvalue: ObjRef) -> usize {
+        match self.heap.get(upvalue) {
+            Obj::Upvalue(Upvalue::Open(slot)) => *slot,
+            _ => unreachable!("Closed upvalue in the open list"),
+        }
+    }
+
+    /////////////////////////////////////////////////////////////////////////
+    // Memory
+    /////////////////////////////////////////////////////////////////////////
+
+    // Everything allocated while running goes through here so a collection
+    // only ever happens between instructions or inside one that's careful
+    // to keep what it's working on reachable.  The compiler allocates
+    // straight from the heap and never collects - what it makes is only
+    // rooted once the script is running.
+    fn alloc(&mut self, obj: Obj) -> ObjRef {
+        if self.stress_gc || self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.heap.alloc(obj)
+    }
+
+    fn alloc_string(&mut self, s: &str) -> Value {
+        Value::Obj(self.alloc(Obj::String(Symbol::intern(s))))
+    }
+
+    // Functions' constants are reached through the closures
+    fn collect_garbage(&mut self) {
+        for value in &self.stack {
+            self.heap.mark_value(*value);
+        }
+        for value in self.globals.values() {
+            self.heap.mark_value(*value);
+        }
+        for frame in &self.frames {
+            self.heap.mark_object(frame.closure);
+        }
+        for upvalue in &self.open_upvalues {
+            self.heap.mark_object(*upvalue);
+        }
+        self.heap.sweep();
+    }
+
+    /////////////////////////////////////////////////////////////////////////
+    // Operators - these follow apply_binary in the evaluator
+    /////////////////////////////////////////////////////////////////////////
+    fn arithmetic(
+        &mut self,
+        int_op: fn(i64, i64) -> Result<i64, &'static str>,
+        float_op: fn(f64, f64) -> f64,
+    ) -> Result<(), LoxError> {
+        let result = match self.numbers()? {
+            (NumberValue::Integer(a), NumberValue::Integer(b)) => {
+                Value::Integer(int_op(a, b).map_err(|msg| self.error(msg, false))?)
+            }
+            (a, b) => Value::Number(float_op(a.as_f64(), b.as_f64())),
+        };
+        self.push(result);
+        Ok(())
+    }
+
+    fn comparison(&mut self, test: fn(Ordering) -> bool) -> Result<(), LoxError> {
+        let (a, b) = self.numbers()?;
+        self.push(Value::Bool(compare_numbers(a, b).is_some_and(test)));
+        Ok(())
+    }
+
+    fn bitwise(&mut self, op: fn(i64, i64) -> Result<i64, &'static str>) -> Result<(), LoxError> {
+        let (left, right) = (self.peek(1), self.peek(0));
+        let a = self.integer(left)?;
+        let b = self.integer(right)?;
+        self.pop();
+        self.pop();
+        let result = op(a, b).map_err(|msg| self.error(msg, false))?;
+        self.push(Value::Integer(result));
+        Ok(())
+    }
+
+    // Pops both operands of a numeric operator, left one first in the error
+    fn numbers(&mut self) -> Result<(NumberValue, NumberValue), LoxError> {
+        let (left, right) = (self.peek(1), self.peek(0));
+        let a = self.number(left)?;
+        let b = self.number(right)?;
+        self.pop();
+        self.pop();
+        Ok((a, b))
+    }
+
+    fn number(&self, value: Value) -> Result<NumberValue, LoxError> {
+        value.as_number().ok_or_else(|| {
+            let msg = format!("Expected number but found {}", self.heap.type_name(value));
+            self.error(&msg, false)
+        })
+    }
+
+    fn integer(&self, value: Value) -> Result<i64, LoxError> {
+        match value {
+            Value::Integer(i) => Ok(i),
+            _ => {
+                let msg = format!("Expected integer but found {}", self.heap.type_name(value));
+                Err(self.error(&msg, false))
+            }
+        }
+    }
+
+    /////////////////////////////////////////////////////////////////////////
+    // Stack and code
+    /////////////////////////////////////////////////////////////////////////
+    fn push(&mut self, value: Value) {
+        self.stack.push(value);
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("Stack underflow")
+    }
+
+    fn peek(&self, distance: usize) -> Value {
+        self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().unwrap()
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frames.last_mut().unwrap();
+        let byte = frame.function.chunk.code[frame.ip];
+        frame.ip += 1;
+        byte
+    }
+
+    fn read_u16(&mut self) -> u16 {
+        let frame = self.frames.last_mut().unwrap();
+        let value = frame.function.chunk.read_u16(frame.ip);
+        frame.ip += 2;
+        value
+    }
+
+    fn read_constant(&mut self) -> Value {
+        let index = self.read_u16() as usize;
+        self.frame().function.chunk.constants[index]
+    }
+
+    fn read_name(&mut self) -> Result<Symbol, LoxError> {
+        let constant = self.read_constant();
+        match self.as_obj(constant) {
+            Some(Obj::String(name)) => Ok(name.clone()),
+            _ => Err(self.error("Name constant isn't a string.", false)),
+        }
+    }
+
+    fn as_obj(&self, value: Value) -> Option<&Obj> {
+        match value {
+            Value::Obj(obj) => Some(self.heap.get(obj)),
+            _ => None,
+        }
+    }
+
+    // Points at the same place the tree walker would for the instruction
+    // being run.  on_operand is for errors about the callee or object
+    // rather than the whole expression.
+    fn error(&self, msg: &str, on_operand: bool) -> LoxError {
+        let frame = self.frame();
+        match frame.function.chunk.site(frame.op_start) {
+            Some(site) => {
+                let span = if on_operand {
+                    site.operand_span
+                } else {
+                    site.span
+                };
+                LoxError::new(site.token.clone(), msg).with_span(span)
+            }
+            None => {
+                let line = frame.function.chunk.lines[frame.op_start];
+                LoxError::new_text_only(Some(line), msg)
+            }
+        }
+    }
+}
+
+fn output_error(error: std::io::Error) -> LoxError {
+    let msg = format!("Output problem: {:?}", error.to_string());
+    LoxError::new_text_only(None, &msg)
+}
+
+#[cfg(test)]
+use crate::parser::evaluate::{run_program, SharedBuffer};
+#[cfg(test)]
+use crate::setup::compile::front_end;
+#[cfg(test)]
+use crate::vm::bytecode_file::write_program;
+#[cfg(test)]
+use std::cell::RefCell;
+
+#[cfg(test)]
+pub fn run_vm_program_with<F>(program: &str, setup: F) -> (String, LoxErrorList)
+where
+    F: FnOnce(&mut Vm),
+{
+    let statements = match front_end(program) {
+        Ok(statements) => statements,
+        Err(errors) => return (String::new(), errors),
+    };
+
+    let buffer = Rc::new(RefCell::new(vec![]));
+    let mut vm = Vm::with_output(Box::new(SharedBuffer(buffer.clone())));
+    setup(&mut vm);
+    let errors = vm.interpret(&statements);
+    let output = String::from_utf8(buffer.borrow().clone()).unwrap();
+    (output, errors)
+}
+
+// Runs each program through both backends and makes sure nobody can tell
+// the difference, right down to where the errors point.  The VM goes again
+// collecting on every allocation so anything it forgets to root shows up.
+#[cfg(test)]
+fn assert_same_as_evaluator(program: &str) {
+    // Whatever the compiler makes has to get past the checks on loading
+    if let Ok(statements) = front_end(program) {
+        let mut heap = Heap::new();
+        if let Ok(function) = Compiler::new(&mut heap).compile(&statements) {
+            let bytes = write_program(&heap, &function).ok().unwrap();
+            assert!(
+                read_program(&mut heap, &bytes).is_ok(),
+                "loading {}",
+                program
+            );
+        }
+    }
+
+    let (expected_output, expected_errors) = run_program(program);
+    let render = |errors: &LoxErrorList| -> Vec<String> {
+        errors.iter().map(|error| error.render(program)).collect()
+    };
+    for stress_gc in [false, true].iter() {
+        let (output, errors) = run_vm_program_with(program, |vm| vm.set_stress_gc(*stress_gc));
+        assert_eq!(expected_output, output, "output of {}", program);
+        assert_eq!(
+            render(&expected_errors),
+            render(&errors),
+            "errors of {}",
+            program
+        );
+    }
+}
+
+#[test]
+pub fn vm_matches_evaluator_test() {
+    let programs = [
+        // Expressions and operators
+        "print 1 + 2 * 3 - 4 / 5;",
+        "print 7 ~/ 2; print -7 ~/ 2; print 7 % -3; print 7.5 % 2;",
+        "print 2 ** 10; print 2 ** -1; print 2.0 ** 3; print 2 ** 3 ** 2;",
+        "print 6 & 3; print 6 | 3; print 6 ^ 3; print ~5; print 1 << 4; print -16 >> 2;",
+        "print 1 < 2; print 2 <= 2; print 3 > 4; print 4 >= 5; print 1 == 1.0;",
+        "print 0/0 == 0/0; print 0/0 != 0/0; print 0/0 >= 1; print nil == false;",
+        "print \"a\" + \"b\"; print \"a\" == \"a\"; print !nil; print -(3);",
+        "print nil or 3; print false and 1; print 1 and 2; print 1 or 2;",
+        "var x = 3; print \"x is ${x} and ${x * 2}!\";",
+        "print 9223372036854775807 + 1;",
+        "print 1 ~/ 0;",
+        "print 1 << 64;",
+        "print 1 + true;",
+        "print -\"a\";",
+        "print ~1.5;",
+        "print 1 & 2.0;",
+        "print \"a\" < 1;",
+        "print -(-9223372036854775807 - 1);",
+        "print 10 ** 19;",
+        "print 1 ** 5000000000; print 0 ** 5000000000; print (-1) ** 5000000001;",
+        "print 2 ** 5000000000;",
+        // Variables and scope
+        "var a = 1; { var a = 2; print a; } print a;",
+        "var a; print a; a = 3; print a; print a = 4;",
+        "print b;",
+        "b = 1;",
+        "{ var a = 1; { var b = a + 1; print b; a = b; } print a; }",
+        // Control flow
+        "for (var i = 0; i < 3; i = i + 1) print i;",
+        "var i = 0; while (i < 5) { if (i % 2 == 0) print i; else print -i; i = i + 1; }",
+        // Functions and closures
+        "fun add(a, b) { return a + b; } print add(1, 2); print add;",
+        "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); } print fib(15);",
+        "fun f() {} print f();",
+        "fun make() { var c = 0; fun inc() { c = c + 1; return c; } return inc; }
+         var a = make(); var b = make(); print a(); print a(); print b();",
+        "var fs; { var x = 1; fun get() { return x; } fun set(v) { x = v; } fs = get; set(5); }
+         print fs();",
+        "fun outer() { var x = \"outer\"; fun middle() { fun inner() { return x; } return inner; }
+         return middle()(); } print outer();",
+        "var closures = nil; for (var i = 0; i < 3; i = i + 1) { fun c() { return i; }
+         if (i == 1) closures = c; } print closures();",
+        "{ fun local(n) { if (n == 0) return 0; return local(n - 1) + n; } print local(4); }",
+        "fun f(a) {} f(1, 2);",
+        "var x = 1; x();",
+        "\"str\"();",
+        "print clock() > 0; print clock;",
+        // Classes
+        "class A { greet() { print \"hi\"; } } A().greet(); print A; print A();",
+        "class P { init(x) { this.x = x; } get() { return this.x; } }
+         var p = P(3); print p.get(); print p.x; p.x = 4; print p.get();",
+        "class C { init() { this.v = 1; return; } } var c = C(); print c.init().v;",
+        "class C { m() { return this; } } var c = C(); var m = c.m; print m() == c;",
+        "class C { m() { fun f() { return this.v; } return f; } } var c = C(); c.v = 9;
+         print c.m()();",
+        "class C {} C().nope;",
+        "class C {} var c = C(); c.m();",
+        "var x = 1; x.y;",
+        "var x = 1; x.y = 2;",
+        "var x = 1; x.y = undefined;",
+        "class C { init(a) {} } C();",
+        "class C {} C(1);",
+        "{ class Local { m() { return Local; } } print Local().m(); }",
+        "class C { f() { return 1; } } var c = C(); c.f = 2; print c.f;",
+        // Inheritance
+        "class A { m() { return \"A\"; } } class B < A {} print B().m();",
+        "class A { m() { return \"A\"; } } class B < A { m() { return \"B\" + super.m(); } }
+         class C < B { m() { return \"C\" + super.m(); } } print C().m();",
+        "class A { init(x) { this.x = x; } } class B < A { init() { super.init(7); } }
+         print B().x;",
+        "class A { m() { print this.name; } } class B < A { m() { var s = super.m; s(); } }
+         var b = B(); b.name = \"b\"; b.m();",
+        "var NotClass = 1; class B < NotClass {}",
+        "class A {} class B < A { m() { super.missing(); } } B().m();",
+        "class B < Nope {}",
+        // Output before an error is kept
+        "print 1; print 2; print nope; print 3;",
+        "fun f() { print \"in f\"; return 1 + nil; } f();",
+        "fun f() { f(); } f();",
+        "fun f(n) { if (n > 0) f(n - 1); else print \"bottom\"; } f(4094); f(4095);",
+        "class A { init(n) { if (n > 0) A(n - 1); } } A(4094); A(4095);",
+    ];
+    for program in programs.iter() {
+        assert_same_as_evaluator(program);
+    }
+}
+
+#[test]
+pub fn trace_test() {
+    let statements = front_end("print 1 + 2;").ok().unwrap();
+    let buffer = Rc::new(RefCell::new(vec![]));
+    let mut vm = Vm::with_output(Box::new(SharedBuffer(buffer.clone())));
+    vm.set_debug_output(Box::new(SharedBuffer(buffer.clone())));
+    vm.set_trace(true);
+    assert_eq!(0, vm.interpret(&statements).len());
+
+    let expected = "          [ <script> ]
+0000    1 Constant            0 1
+          [ <script> ][ 1 ]
+0003    | Constant            1 2
+          [ <script> ][ 1 ][ 2 ]
+0006    | Add
+          [ <script> ][ 3 ]
+0007    | Print
+3
+          [ <script> ]
+0008    | Nil
+          [ <script> ][ nil ]
+0009    | Return
+";
+    assert_eq!(
+        expected,
+        String::from_utf8(buffer.borrow().clone()).unwrap()
+    );
+}
+
+#[test]
+pub fn gc_test() {
+    // Every Node points at itself so reference counting would never free
+    // one.  The collector has to.
+    let program = "
+        class Node { init(next) { this.next = next; this.me = this; } }
+        var keep = Node(nil);
+        fun churn(n) {
+            var last = nil;
+            for (var i = 0; i < n; i = i + 1) {
+                var node = Node(nil);
+                fun cycle() { return node; }
+                node.cycle = cycle;
+                last = Node(last);
+                if (i % 100 == 0) last = nil;
+            }
+            return last;
+        }
+        keep.next = churn(3000);
+        print keep.me == keep;
+        print keep.next.next.me == keep.next.next;
+        print \"${keep.next == nil} done\";";
+
+    let statements = front_end(program).ok().unwrap();
+    let buffer = Rc::new(RefCell::new(vec![]));
+    let mut vm = Vm::with_output(Box::new(SharedBuffer(buffer.clone())));
+    assert_eq!(0, vm.interpret(&statements).len());
+    let output = String::from_utf8(buffer.borrow().clone()).unwrap();
+    assert_eq!("true\ntrue\nfalse done\n", output);
+
+    let stats = vm.heap_stats();
+    assert!(stats.collections > 0);
+    assert!(stats.freed > 10_000);
+    // Only the last hundred or so nodes are still reachable
+    assert!(stats.live < 2048, "{:?}", stats);
+    assert_eq!(stats.allocated, stats.live + stats.freed);
+}
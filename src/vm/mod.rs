@@ -0,0 +1,6 @@
+pub mod bytecode_file;
+pub mod chunk;
+pub mod compiler;
+pub mod disassemble;
+pub mod machine;
+pub mod value;
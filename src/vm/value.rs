@@ -0,0 +1,333 @@
+use crate::lox_error;
+use crate::parser;
+use crate::scanner;
+use crate::vm;
+
+use lox_error::lox_error::LoxError;
+use parser::evaluate::compare_numbers;
+use scanner::symbol::Symbol;
+use scanner::token_type::NumberValue;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::rc::Rc;
+use vm::chunk::Chunk;
+
+// A handle on an object living in the Heap.  It's just an index so it's
+// cheap to copy around and values holding one can be Copy too.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub struct ObjRef(usize);
+
+// What the VM pushes around on its stack.  Anything bigger than a number
+// lives in the heap and the value just points at it.
+#[derive(Clone, Copy, Debug)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Integer(i64),
+    Obj(ObjRef),
+}
+
+impl Value {
+    pub fn is_falsey(&self) -> bool {
+        matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    pub fn as_number(&self) -> Option<NumberValue> {
+        match self {
+            Value::Number(n) => Some(NumberValue::Float(*n)),
+            Value::Integer(i) => Some(NumberValue::Integer(*i)),
+            _ => None,
+        }
+    }
+}
+
+impl From<NumberValue> for Value {
+    fn from(number: NumberValue) -> Self {
+        match number {
+            NumberValue::Integer(i) => Value::Integer(i),
+            NumberValue::Float(f) => Value::Number(f),
+        }
+    }
+}
+
+// A compiled function.  The top level script is one too with an empty name.
+pub struct Function {
+    pub name: String,
+    pub arity: usize,
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+}
+
+// The signature Rust code has to provide to be callable from the VM
+pub type NativeFn = dyn Fn(&[Value]) -> Result<Value, LoxError>;
+
+pub struct Native {
+    pub name: String,
+    pub arity: usize,
+    pub function: Box<NativeFn>,
+}
+
+// A function together with the variables it captured.  This is what
+// actually gets called - bare Functions only live in constant pools.
+pub struct Closure {
+    pub function: Rc<Function>,
+    pub upvalues: Vec<ObjRef>,
+}
+
+// A captured variable.  While the variable is still on the stack the
+// upvalue just knows which slot it's in.  When the variable goes out of
+// scope its value moves in here.
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+// Methods are copied down from the superclass when a class inherits so
+// finding one never has to walk up the chain
+pub struct Class {
+    pub name: Symbol,
+    pub methods: HashMap<Symbol, ObjRef>,
+}
+
+pub struct Instance {
+    pub class: ObjRef,
+    pub fields: HashMap<Symbol, Value>,
+}
+
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: ObjRef,
+}
+
+// Strings are interned so two string objects with the same contents share
+// their text and compare by pointer
+pub enum Obj {
+    String(Symbol),
+    Function(Rc<Function>),
+    Native(Rc<Native>),
+    Closure(Closure),
+    Upvalue(Upvalue),
+    Class(Class),
+    Instance(Instance),
+    BoundMethod(BoundMethod),
+}
+
+// Counts kept by the heap so we can see what the collector is up to
+#[derive(Clone, Copy, Default, Debug)]
+pub struct HeapStats {
+    pub allocated: usize,
+    pub freed: usize,
+    pub collections: usize,
+    pub live: usize,
+    pub peak: usize,
+}
+
+// Don't bother collecting until there are at least this many objects
+const MIN_NEXT_GC: usize = 1024;
+
+// Every object the VM or compiler creates lives here.  Nothing is freed
+// until the VM asks for a collection, handing over its roots with
+// mark_value() and mark_object() and then calling sweep().  Freed slots
+// get reused by later allocations.
+pub struct Heap {
+    objects: Vec<Option<Obj>>,
+    marks: Vec<bool>,
+    free: Vec<usize>,
+    // Objects that are marked but whose references haven't been followed
+    gray: Vec<ObjRef>,
+    next_gc: usize,
+    stats: HeapStats,
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Heap {
+            objects: vec![],
+            marks: vec![],
+            free: vec![],
+            gray: vec![],
+            next_gc: MIN_NEXT_GC,
+            stats: HeapStats::default(),
+        }
+    }
+
+    pub fn alloc(&mut self, obj: Obj) -> ObjRef {
+        self.stats.allocated += 1;
+        self.stats.live += 1;
+        self.stats.peak = self.stats.peak.max(self.stats.live);
+        match self.free.pop() {
+            Some(slot) => {
+                self.objects[slot] = Some(obj);
+                ObjRef(slot)
+            }
+            None => {
+                self.objects.push(Some(obj));
+                self.marks.push(false);
+                ObjRef(self.objects.len() - 1)
+            }
+        }
+    }
+
+    pub fn alloc_string(&mut self, s: &str) -> Value {
+        Value::Obj(self.alloc(Obj::String(Symbol::intern(s))))
+    }
+
+    // Getting at a freed object means the VM missed a root somewhere
+    pub fn get(&self, obj: ObjRef) -> &Obj {
+        self.objects[obj.0]
+            .as_ref()
+            .expect("Object used after being freed")
+    }
+
+    pub fn get_mut(&mut self, obj: ObjRef) -> &mut Obj {
+        self.objects[obj.0]
+            .as_mut()
+            .expect("Object used after being freed")
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        self.stats
+    }
+
+    // The heap doubles between collections so the work done collecting
+    // stays in proportion to the work done allocating
+    pub fn should_collect(&self) -> bool {
+        self.stats.live >= self.next_gc
+    }
+
+    pub fn mark_value(&mut self, value: Value) {
+        if let Value::Obj(obj) = value {
+            self.mark_object(obj);
+        }
+    }
+
+    pub fn mark_object(&mut self, obj: ObjRef) {
+        if !self.marks[obj.0] {
+            self.marks[obj.0] = true;
+            self.gray.push(obj);
+        }
+    }
+
+    // Follows references out from everything marked so far and then frees
+    // whatever wasn't reached
+    pub fn sweep(&mut self) {
+        while let Some(obj) = self.gray.pop() {
+            for value in self.references(obj) {
+                self.mark_value(value);
+            }
+        }
+
+        for slot in 0..self.objects.len() {
+            if self.marks[slot] {
+                self.marks[slot] = false;
+            } else if self.objects[slot].take().is_some() {
+                self.free.push(slot);
+                self.stats.freed += 1;
+                self.stats.live -= 1;
+            }
+        }
+        self.stats.collections += 1;
+        self.next_gc = MIN_NEXT_GC.max(self.stats.live * 2);
+    }
+
+    fn references(&self, obj: ObjRef) -> Vec<Value> {
+        match self.get(obj) {
+            Obj::String(_) | Obj::Native(_) => vec![],
+            Obj::Function(function) => function.chunk.constants.clone(),
+            Obj::Closure(closure) => {
+                let mut references = closure.function.chunk.constants.clone();
+                references.extend(closure.upvalues.iter().map(|u| Value::Obj(*u)));
+                references
+            }
+            Obj::Upvalue(Upvalue::Open(_)) => vec![],
+            Obj::Upvalue(Upvalue::Closed(value)) => vec![*value],
+            Obj::Class(class) => class.methods.values().map(|m| Value::Obj(*m)).collect(),
+            Obj::Instance(instance) => {
+                let mut references: Vec<Value> = instance.fields.values().copied().collect();
+                references.push(Value::Obj(instance.class));
+                references
+            }
+            Obj::BoundMethod(bound) => vec![bound.receiver, Value::Obj(bound.method)],
+        }
+    }
+
+    pub fn as_str(&self, value: Value) -> Option<&str> {
+        match value {
+            Value::Obj(obj) => match self.get(obj) {
+                Obj::String(s) => Some(s),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    // The name of a class, function or bound method for printing
+    fn function_name(&self, obj: ObjRef) -> &str {
+        match self.get(obj) {
+            Obj::Closure(closure) => &closure.function.name,
+            Obj::Function(function) => &function.name,
+            Obj::BoundMethod(bound) => self.function_name(bound.method),
+            _ => "",
+        }
+    }
+
+    // Exactly what the tree walker prints for the same value
+    pub fn to_string(&self, value: Value) -> String {
+        match value {
+            Value::Nil => "nil".to_string(),
+            Value::Bool(b) => format!("{}", b),
+            Value::Number(n) => format!("{}", n),
+            Value::Integer(i) => format!("{}", i),
+            Value::Obj(obj) => match self.get(obj) {
+                Obj::String(s) => s.to_string(),
+                Obj::Native(native) => format!("<native fn {}>", native.name),
+                Obj::Class(class) => class.name.to_string(),
+                Obj::Instance(instance) => match self.get(instance.class) {
+                    Obj::Class(class) => format!("{} instance", class.name),
+                    _ => "instance".to_string(),
+                },
+                Obj::Upvalue(_) => "upvalue".to_string(),
+                Obj::Closure(_) | Obj::Function(_) | Obj::BoundMethod(_) => {
+                    format!("<fn {}>", self.function_name(obj))
+                }
+            },
+        }
+    }
+
+    // Names for error messages - these match the tree walker's too
+    pub fn type_name(&self, value: Value) -> &'static str {
+        match value {
+            Value::Nil => "nil",
+            Value::Bool(_) => "bool",
+            Value::Number(_) => "number",
+            Value::Integer(_) => "integer",
+            Value::Obj(obj) => match self.get(obj) {
+                Obj::String(_) => "string",
+                Obj::Class(_) => "class",
+                Obj::Instance(_) => "instance",
+                Obj::Upvalue(_) => "upvalue",
+                Obj::Function(_) | Obj::Native(_) | Obj::Closure(_) | Obj::BoundMethod(_) => {
+                    "function"
+                }
+            },
+        }
+    }
+
+    // Numbers compare by value whatever sort they are, strings by their
+    // interned text and every other object only equals itself
+    pub fn values_equal(&self, left: Value, right: Value) -> bool {
+        match (left, right) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Obj(a), Value::Obj(b)) => match (self.get(a), self.get(b)) {
+                (Obj::String(a), Obj::String(b)) => a == b,
+                _ => a == b,
+            },
+            _ => match (left.as_number(), right.as_number()) {
+                (Some(a), Some(b)) => compare_numbers(a, b) == Some(Ordering::Equal),
+                _ => false,
+            },
+        }
+    }
+}